@@ -0,0 +1,469 @@
+use crate::Color;
+use serde_derive::{Deserialize, Serialize};
+use tsify::Tsify;
+
+/// A point in one of the supported color spaces
+pub(crate) type Point = [f32; 3];
+
+/// D65 reference white used for the CIE spaces
+const WHITE: Point = [0.95047, 1.0, 1.08883];
+const DELTA: f32 = 6.0 / 29.0;
+/// The most Weiszfeld steps taken for a geometric median
+const MEDIAN_ITERATIONS: usize = 50;
+/// A geometric median has converged once a step moves it less than this
+const MEDIAN_TOLERANCE: f32 = 1e-3;
+
+/// The color space clusters are formed in. Whatever the space the final
+/// clusters are always reported as sRGB `Color`s
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize, Tsify)]
+pub enum ColorSpace {
+    /// Plain sRGB channels
+    #[default]
+    Rgb,
+    /// CIE LCh, the polar form of CIE Lab, as lightness, chroma and hue in
+    /// degrees. Hue is circular so is handled as such in distances and means
+    Lch,
+    /// The HSV cone, as value and chroma (saturation times value) from `0` to
+    /// `100` and hue in degrees. Distances and means treat it as a cylinder
+    /// just as LCh, so colors group by hue without perceptual weighting
+    Hsv,
+}
+
+/// How the distance between two points is measured within a color space
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize, Tsify)]
+pub enum DistanceMetric {
+    /// The straight line distance, as `ColorSpace` measures it
+    #[default]
+    Euclidean,
+    /// The largest absolute difference of any one channel, giving cube
+    /// shaped cells as hardware with a threshold per channel does. Only
+    /// supported in `ColorSpace::Rgb`
+    Chebyshev,
+}
+
+impl DistanceMetric {
+    /// The distance between two points in the given space
+    ///
+    /// # Arguments
+    /// * `space` - the color space the points are in
+    /// * `p` - first point
+    /// * `q` - second point
+    pub(crate) fn distance(self, space: ColorSpace, p: &Point, q: &Point) -> f32 {
+        match self {
+            DistanceMetric::Euclidean => space.distance(p, q),
+            DistanceMetric::Chebyshev => chebyshev_distance(p, q),
+        }
+    }
+
+    /// The squared distance between two points in the given space
+    ///
+    /// # Arguments
+    /// * `space` - the color space the points are in
+    /// * `p` - first point
+    /// * `q` - second point
+    pub(crate) fn squared_distance(self, space: ColorSpace, p: &Point, q: &Point) -> f64 {
+        match self {
+            DistanceMetric::Euclidean => space.squared_distance(p, q),
+            DistanceMetric::Chebyshev => (chebyshev_distance(p, q) as f64).powi(2),
+        }
+    }
+
+    /// Index of, and distance to, the point nearest to `point` as
+    /// `ColorSpace::nearest` gives it but measured with this metric
+    ///
+    /// # Arguments
+    /// * `space` - the color space the points are in
+    /// * `point` - the point to match
+    /// * `points` - the candidates, must not be empty
+    pub(crate) fn nearest(
+        self,
+        space: ColorSpace,
+        point: &Point,
+        points: &[Point],
+    ) -> (usize, f32) {
+        match self {
+            DistanceMetric::Euclidean => space.nearest(point, points),
+            DistanceMetric::Chebyshev => self.nearest_by_distance(space, point, points),
+        }
+    }
+
+    /// Index of, and distance to, the point nearest to `point` as
+    /// `ColorSpace::nearest_by_distance` gives it but measured with this metric
+    ///
+    /// # Arguments
+    /// * `space` - the color space the points are in
+    /// * `point` - the point to match
+    /// * `points` - the candidates, must not be empty
+    pub(crate) fn nearest_by_distance(
+        self,
+        space: ColorSpace,
+        point: &Point,
+        points: &[Point],
+    ) -> (usize, f32) {
+        if self == DistanceMetric::Euclidean {
+            return space.nearest_by_distance(point, points);
+        }
+        let mut nearest = (0, f32::INFINITY);
+
+        for (i, candidate) in points.iter().enumerate() {
+            let distance = self.distance(space, point, candidate);
+            if distance < nearest.1 {
+                nearest = (i, distance);
+            }
+        }
+
+        nearest
+    }
+}
+
+impl ColorSpace {
+    /// Convert an sRGB color into a point in this space
+    ///
+    /// # Arguments
+    /// * `color` - the color to convert
+    pub(crate) fn to_point(self, color: &Color) -> Point {
+        let rgb = [color.r as f32, color.g as f32, color.b as f32];
+
+        match self {
+            ColorSpace::Rgb => rgb,
+            ColorSpace::Lch => {
+                let [l, a, b] = xyz_to_lab(rgb_to_xyz(rgb));
+                let hue = b.atan2(a).to_degrees();
+                [l, (a * a + b * b).sqrt(), hue.rem_euclid(360.0)]
+            }
+            ColorSpace::Hsv => rgb_to_hsv(rgb),
+        }
+    }
+
+    /// Convert a point in this space back to sRGB channels without rounding,
+    /// clamping anything outside of the sRGB gamut
+    ///
+    /// # Arguments
+    /// * `point` - the point to convert
+    pub(crate) fn to_rgb(self, point: Point) -> Point {
+        let rgb = match self {
+            ColorSpace::Rgb => point,
+            ColorSpace::Lch => {
+                let [l, c, h] = point;
+                let h = h.to_radians();
+                xyz_to_rgb(lab_to_xyz([l, c * h.cos(), c * h.sin()]))
+            }
+            ColorSpace::Hsv => hsv_to_rgb(point),
+        };
+        rgb.map(|v| v.clamp(0.0, 255.0))
+    }
+
+    /// Convert a point in this space back to an sRGB color, clamping anything
+    /// outside of the sRGB gamut
+    ///
+    /// # Arguments
+    /// * `point` - the point to convert
+    pub(crate) fn to_color(self, point: Point) -> Color {
+        let rgb = self.to_rgb(point);
+        let channel = |v: f32| v.round() as i32;
+
+        Color {
+            r: channel(rgb[0]),
+            g: channel(rgb[1]),
+            b: channel(rgb[2]),
+        }
+    }
+
+    /// The distance between two points in this space. For LCh and HSV the hue
+    /// term is the chord between the two hues scaled by their chroma, so hues
+    /// either side of 0° are close and hue matters less for unsaturated colors
+    ///
+    /// # Arguments
+    /// * `p` - first point
+    /// * `q` - second point
+    pub(crate) fn distance(self, p: &Point, q: &Point) -> f32 {
+        match self {
+            ColorSpace::Rgb => {
+                ((p[0] - q[0]).powi(2) + (p[1] - q[1]).powi(2) + (p[2] - q[2]).powi(2)).sqrt()
+            }
+            ColorSpace::Lch | ColorSpace::Hsv => {
+                let hue_term = 2.0 * p[1] * q[1] * (1.0 - (p[2] - q[2]).to_radians().cos());
+                ((p[0] - q[0]).powi(2) + (p[1] - q[1]).powi(2) + hue_term.max(0.0)).sqrt()
+            }
+        }
+    }
+
+    /// The squared distance between two points in this space. RGB points
+    /// always have whole channels so this is exact for them
+    ///
+    /// # Arguments
+    /// * `p` - first point
+    /// * `q` - second point
+    pub(crate) fn squared_distance(self, p: &Point, q: &Point) -> f64 {
+        match self {
+            ColorSpace::Rgb => rgb_squared_distance(p, q) as f64,
+            ColorSpace::Lch | ColorSpace::Hsv => (self.distance(p, q) as f64).powi(2),
+        }
+    }
+
+    /// Index of, and distance to, the point nearest to `point`. Ties go to the
+    /// earliest point. RGB points are compared by their exact integer squared
+    /// distance so the choice never depends on float rounding
+    ///
+    /// # Arguments
+    /// * `point` - the point to match
+    /// * `points` - the candidates, must not be empty
+    pub(crate) fn nearest(self, point: &Point, points: &[Point]) -> (usize, f32) {
+        if self == ColorSpace::Rgb {
+            return points
+                .iter()
+                .map(|candidate| rgb_squared_distance(point, candidate))
+                .enumerate()
+                .min_by_key(|&(_, d2)| d2)
+                .map_or((0, f32::INFINITY), |(i, d2)| (i, (d2 as f32).sqrt()));
+        }
+
+        self.nearest_by_distance(point, points)
+    }
+
+    /// Index of, and distance to, the color of `candidates` nearest to `color`
+    /// as `nearest` finds it for points in this space. Only available with the
+    /// `test-utils` feature
+    ///
+    /// # Arguments
+    /// * `color` - the color to match
+    /// * `candidates` - the colors to pick from, must not be empty
+    #[cfg(feature = "test-utils")]
+    pub fn nearest_color(self, color: &Color, candidates: &[Color]) -> (usize, f32) {
+        let points: Vec<Point> = candidates.iter().map(|c| self.to_point(c)).collect();
+        self.nearest(&self.to_point(color), &points)
+    }
+
+    /// Index of, and distance to, the point nearest to `point` as `nearest`
+    /// gives, always comparing float distances. For RGB points that don't
+    /// have whole channels, such as jittered ones
+    ///
+    /// # Arguments
+    /// * `point` - the point to match
+    /// * `points` - the candidates, must not be empty
+    pub(crate) fn nearest_by_distance(self, point: &Point, points: &[Point]) -> (usize, f32) {
+        let mut nearest = (0, f32::INFINITY);
+
+        for (i, candidate) in points.iter().enumerate() {
+            let distance = self.distance(point, candidate);
+            if distance < nearest.1 {
+                nearest = (i, distance);
+            }
+        }
+
+        nearest
+    }
+
+    /// The weighted mean of some points in this space. For LCh and HSV the hue
+    /// is a circular mean weighted by both the weight and the chroma of each point,
+    /// so near-grey colors with an arbitrary hue barely affect it
+    ///
+    /// # Arguments
+    /// * `points` - the points with their weights, the total weight must not be 0
+    pub(crate) fn weighted_mean<'a>(self, points: impl Iterator<Item = (&'a Point, f64)>) -> Point {
+        let mut sums = [0.0_f64; 3];
+        let (mut hue_x, mut hue_y) = (0.0_f64, 0.0_f64);
+        let mut total = 0.0_f64;
+
+        for (point, weight) in points {
+            for (sum, v) in sums.iter_mut().zip(point) {
+                *sum += *v as f64 * weight;
+            }
+            if self != ColorSpace::Rgb {
+                let (sin, cos) = (point[2] as f64).to_radians().sin_cos();
+                hue_x += cos * point[1] as f64 * weight;
+                hue_y += sin * point[1] as f64 * weight;
+            }
+            total += weight;
+        }
+
+        let mut mean = sums.map(|sum| (sum / total) as f32);
+        if self != ColorSpace::Rgb {
+            mean[2] = (hue_y.atan2(hue_x).to_degrees() as f32).rem_euclid(360.0);
+        }
+        mean
+    }
+
+    /// The weighted geometric median of some points in this space, the point
+    /// with the least weighted sum of distances to them, found with
+    /// Weiszfeld's algorithm starting from the weighted mean. Each step is
+    /// the mean weighted by the inverse distances, so LCh and HSV hues stay
+    /// circular. Points the estimate lands on have no inverse distance, so
+    /// as Vardi and Zhang modify it the estimate stays put if their weight
+    /// outweighs the pull of the rest and otherwise only moves part of a step
+    ///
+    /// # Arguments
+    /// * `points` - the points with their weights, the total weight must not be 0
+    pub(crate) fn geometric_median(self, points: &[(&Point, f64)]) -> Point {
+        let mut median = self.weighted_mean(points.iter().copied());
+
+        for _ in 0..MEDIAN_ITERATIONS {
+            let mut landed = 0.0;
+            let mut weighted: Vec<(&Point, f64)> = vec![];
+            for &(point, weight) in points {
+                let distance = self.distance(point, &median);
+                if distance <= MEDIAN_TOLERANCE {
+                    landed += weight;
+                } else {
+                    weighted.push((point, weight / distance as f64));
+                }
+            }
+            let inverse_total: f64 = weighted.iter().map(|(_, w)| w).sum();
+            if inverse_total <= 0.0 {
+                break;
+            }
+
+            let step = self.weighted_mean(weighted.into_iter());
+            let next = if landed > 0.0 {
+                let pull = self.distance(&step, &median) as f64 * inverse_total;
+                if pull <= landed {
+                    break;
+                }
+                let stay = landed / pull;
+                self.weighted_mean([(&step, 1.0 - stay), (&median, stay)].into_iter())
+            } else {
+                step
+            };
+
+            let moved = self.distance(&next, &median);
+            median = next;
+            if moved < MEDIAN_TOLERANCE {
+                break;
+            }
+        }
+
+        median
+    }
+}
+
+/// The squared distance between two RGB points in integers, their channels
+/// are whole numbers from 0 to 255 so this can't overflow
+fn rgb_squared_distance(p: &Point, q: &Point) -> i32 {
+    p.iter()
+        .zip(q)
+        .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+        .sum()
+}
+
+/// The largest absolute difference between any one channel of two points
+fn chebyshev_distance(p: &Point, q: &Point) -> f32 {
+    p.iter()
+        .zip(q)
+        .map(|(a, b)| (a - b).abs())
+        .fold(0.0, f32::max)
+}
+
+/// Linearize an sRGB channel in `0..=255` to linear-light `0.0..=1.0`
+pub(crate) fn to_linear(channel: f32) -> f32 {
+    let c = channel / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear-light channel in `0.0..=1.0` back to sRGB `0..=255`
+pub(crate) fn from_linear(channel: f32) -> f32 {
+    let c = if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    };
+    c * 255.0
+}
+
+/// sRGB channels in `0..=255` to value, chroma and hue, as an HSV point
+fn rgb_to_hsv(rgb: Point) -> Point {
+    let [r, g, b] = rgb.map(|c| c / 255.0);
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / chroma)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+
+    [max * 100.0, chroma * 100.0, hue.rem_euclid(360.0)]
+}
+
+/// An HSV point back to sRGB channels in `0..=255`, a chroma above the value
+/// is limited to the value
+fn hsv_to_rgb(point: Point) -> Point {
+    let value = point[0] / 100.0;
+    let chroma = (point[1] / 100.0).clamp(0.0, value.max(0.0));
+    let h = point[2].rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+
+    let [r, g, b] = match h as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    let m = value - chroma;
+
+    [r + m, g + m, b + m].map(|c| c * 255.0)
+}
+
+fn rgb_to_xyz(rgb: Point) -> Point {
+    let [r, g, b] = rgb.map(to_linear);
+    [
+        0.4124 * r + 0.3576 * g + 0.1805 * b,
+        0.2126 * r + 0.7152 * g + 0.0722 * b,
+        0.0193 * r + 0.1192 * g + 0.9505 * b,
+    ]
+}
+
+fn xyz_to_rgb(xyz: Point) -> Point {
+    let [x, y, z] = xyz;
+    [
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    ]
+    .map(from_linear)
+}
+
+fn xyz_to_lab(xyz: Point) -> Point {
+    let f = |t: f32| {
+        if t > DELTA.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let [fx, fy, fz] = [
+        f(xyz[0] / WHITE[0]),
+        f(xyz[1] / WHITE[1]),
+        f(xyz[2] / WHITE[2]),
+    ];
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_xyz(lab: Point) -> Point {
+    let f_inv = |t: f32| {
+        if t > DELTA {
+            t.powi(3)
+        } else {
+            3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+        }
+    };
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+
+    [
+        WHITE[0] * f_inv(fx),
+        WHITE[1] * f_inv(fy),
+        WHITE[2] * f_inv(fz),
+    ]
+}
@@ -0,0 +1,105 @@
+use crate::color_space::{ColorSpace, Point};
+
+/// Fuzzy c-means over a set of weighted points, where every point belongs to
+/// every centre to a degree given by how near it is compared to the others
+pub(crate) struct FuzzyCMeans<'a> {
+    points: &'a [Point],
+    weights: &'a [f64],
+    space: ColorSpace,
+    /// The fuzziness exponent, above `1.0`. Values near `1.0` approach hard
+    /// k-means and larger values share points more evenly
+    m: f64,
+}
+
+impl<'a> FuzzyCMeans<'a> {
+    /// # Arguments
+    /// * `points` - The points to cluster
+    /// * `weights` - The weight of each point
+    /// * `space` - The color space `points` are in
+    /// * `m` - The fuzziness exponent, must be above `1.0`
+    pub fn new(points: &'a [Point], weights: &'a [f64], space: ColorSpace, m: f32) -> Self {
+        FuzzyCMeans {
+            points,
+            weights,
+            space,
+            m: m as f64,
+        }
+    }
+
+    /// The membership of a point in each centre, summing to `1.0`. A point
+    /// sitting exactly on a centre belongs only to the first such centre
+    ///
+    /// # Arguments
+    /// * `point` - The point to find the memberships of
+    /// * `centres` - The current centres, must not be empty
+    fn memberships(&self, point: &Point, centres: &[Point]) -> Vec<f64> {
+        let d2: Vec<f64> = centres
+            .iter()
+            .map(|c| self.space.squared_distance(point, c))
+            .collect();
+
+        if let Some(on) = d2.iter().position(|&d| d == 0.0) {
+            let mut memberships = vec![0.0; centres.len()];
+            memberships[on] = 1.0;
+            return memberships;
+        }
+
+        let exponent = 1.0 / (self.m - 1.0);
+        d2.iter()
+            .map(|dj| 1.0 / d2.iter().map(|dk| (dj / dk).powf(exponent)).sum::<f64>())
+            .collect()
+    }
+
+    /// The fuzzy objective for the given centres, the sum over every point and
+    /// centre of the squared distance between them weighted by the point's
+    /// weight and its membership raised to `m`
+    ///
+    /// # Arguments
+    /// * `centres` - The centres to score, must not be empty
+    pub fn objective(&self, centres: &[Point]) -> f32 {
+        self.points
+            .iter()
+            .zip(self.weights)
+            .map(|(point, weight)| {
+                self.memberships(point, centres)
+                    .iter()
+                    .zip(centres)
+                    .map(|(u, c)| u.powf(self.m) * self.space.squared_distance(point, c))
+                    .sum::<f64>()
+                    * weight
+            })
+            .sum::<f64>() as f32
+    }
+
+    /// Move every centre to the mean of all the points weighted by their
+    /// weight and membership raised to `m`. A centre no point belongs to at
+    /// all stays where it is
+    ///
+    /// # Arguments
+    /// * `centres` - The current centres, must not be empty
+    pub fn update(&self, centres: &[Point]) -> Vec<Point> {
+        let memberships: Vec<Vec<f64>> = self
+            .points
+            .iter()
+            .map(|point| self.memberships(point, centres))
+            .collect();
+
+        centres
+            .iter()
+            .enumerate()
+            .map(|(j, centre)| {
+                let weighted = self
+                    .points
+                    .iter()
+                    .zip(self.weights)
+                    .zip(&memberships)
+                    .map(|((point, weight), u)| (point, weight * u[j].powf(self.m)));
+                if weighted.clone().all(|(_, w)| w == 0.0) {
+                    *centre
+                } else {
+                    self.space.weighted_mean(weighted)
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,72 @@
+use crate::Color;
+
+/// Bits per channel of the colors being indexed
+const BITS: u32 = 8;
+
+/// The position of a color along a Hilbert curve filling the RGB cube. Colors
+/// close together on the curve are always close together in the cube, so
+/// sorting by this index puts similar colors next to each other
+///
+/// Uses Skilling's transpose algorithm, "Programming the Hilbert curve" (2004)
+///
+/// # Arguments
+/// * `color` - the color to index, each channel must be from 0 to 255
+pub(crate) fn hilbert_index(color: &Color) -> u32 {
+    let mut x = [color.r as u32, color.g as u32, color.b as u32];
+
+    // undo the excess work of the inverse transform
+    let mut q = 1 << (BITS - 1);
+    while q > 1 {
+        let p = q - 1;
+        for i in 0..3 {
+            if x[i] & q != 0 {
+                x[0] ^= p;
+            } else {
+                let t = (x[0] ^ x[i]) & p;
+                x[0] ^= t;
+                x[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+
+    // gray encode
+    x[1] ^= x[0];
+    x[2] ^= x[1];
+    let mut t = 0;
+    let mut q = 1 << (BITS - 1);
+    while q > 1 {
+        if x[2] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    x.iter_mut().for_each(|v| *v ^= t);
+
+    // interleave the transposed bits, most significant first
+    (0..BITS).rev().fold(0, |index, bit| {
+        x.iter()
+            .fold(index, |index, v| (index << 1) | ((v >> bit) & 1))
+    })
+}
+
+/// Split indices sorted along the curve into `k` contiguous segments at the
+/// `k - 1` largest jumps between neighbouring indices, ties going to the
+/// earliest jump. Returns the start of each segment
+///
+/// # Arguments
+/// * `sorted` - Hilbert indices in ascending order
+/// * `k` - How many segments to make, at most `sorted.len()`
+pub(crate) fn segment_starts(sorted: &[u32], k: usize) -> Vec<usize> {
+    let mut jumps: Vec<usize> = (1..sorted.len()).collect();
+    jumps.sort_by(|&a, &b| {
+        let gap = |i: usize| sorted[i] - sorted[i - 1];
+        gap(b).cmp(&gap(a)).then(a.cmp(&b))
+    });
+
+    let mut starts: Vec<usize> = std::iter::once(0)
+        .chain(jumps.into_iter().take(k.saturating_sub(1)))
+        .collect();
+    starts.sort();
+    starts
+}
@@ -0,0 +1,218 @@
+use crate::color_space::{ColorSpace, DistanceMetric, Point};
+use crate::random::RandomSource;
+use serde_derive::{Deserialize, Serialize};
+use tsify::Tsify;
+
+/// Number of sampling rounds k-means|| makes over the colors
+const PARALLEL_ROUNDS: usize = 5;
+
+/// How the initial k clusters are picked from the working colors
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize, Tsify)]
+pub enum InitMethod {
+    /// Distinct colors picked uniformly at random
+    #[default]
+    Random,
+    /// k-means++, each pick favours colors far from those already picked
+    KmeansPlusPlus,
+    /// k-means|| (scalable k-means++), oversamples candidates over a few
+    /// rounds then reduces them to k with k-means++. Much faster than
+    /// k-means++ for images with very many distinct colors
+    KmeansParallel,
+    /// No randomness at all, colors are picked evenly spaced by cumulative
+    /// frequency through the working colors. The same image and config give
+    /// the same palette in every version of the module, so this is the one to
+    /// use for golden tests
+    Deterministic,
+}
+
+/// Pick up to `a` indices evenly spaced by cumulative weight, the i-th pick
+/// being the point the middle of the i-th of `a` equal slices of the total
+/// weight falls in. Picks always move forward so each is distinct
+///
+/// # Arguments
+/// * `weights` - The weight of each point, in the order to walk them
+/// * `a` - How many points to pick
+pub(crate) fn evenly_spaced(weights: &[f64], a: usize) -> Vec<usize> {
+    let a = a.min(weights.len());
+    let total: f64 = weights.iter().sum();
+    let mut picked: Vec<usize> = Vec::with_capacity(a);
+    let (mut index, mut cumulative) = (0, 0.0);
+
+    for i in 0..a {
+        let target = (i as f64 + 0.5) / a as f64 * total;
+        while index + 1 < weights.len() && cumulative + weights[index] <= target {
+            cumulative += weights[index];
+            index += 1;
+        }
+
+        let earliest = picked.last().map_or(0, |last| last + 1);
+        let latest = weights.len() - (a - i);
+        picked.push(index.clamp(earliest, latest));
+    }
+
+    picked
+}
+
+/// Pick up to `a` indices of `points` with k-means++ seeding, where each pick
+/// is made with probability proportional to its weight times its squared
+/// distance from the nearest pick so far
+///
+/// # Arguments
+/// * `points` - The points to pick from
+/// * `weights` - The weight of each point
+/// * `space` - The color space `points` are in
+/// * `metric` - How distances between `points` are measured
+/// * `a` - How many points to pick
+/// * `picked` - Any points already picked, these are included in the result
+/// * `rng` - The source of randomness for the picks
+pub(crate) fn kmeans_plus_plus(
+    points: &[Point],
+    weights: &[f64],
+    space: ColorSpace,
+    metric: DistanceMetric,
+    a: usize,
+    mut picked: Vec<usize>,
+    rng: &mut dyn RandomSource,
+) -> Vec<usize> {
+    let a = a.min(points.len());
+    let mut min_d2 = min_squared_distances(points, &picked, space, metric);
+
+    while picked.len() < a {
+        // the first pick is by weight alone, there's nothing to be far from
+        let scores: Vec<f64> = if picked.is_empty() {
+            weights.to_vec()
+        } else {
+            min_d2.iter().zip(weights).map(|(d2, w)| d2 * w).collect()
+        };
+        let next = match weighted_pick(&scores, rng) {
+            Some(next) => next,
+            // every remaining point coincides with a pick, take any unpicked
+            None => match (0..points.len()).find(|i| !picked.contains(i)) {
+                Some(next) => next,
+                None => break,
+            },
+        };
+
+        picked.push(next);
+        update_min_squared_distances(points, &points[next], space, metric, &mut min_d2);
+    }
+
+    picked
+}
+
+/// Pick up to `a` indices of `points` with k-means|| seeding. Each round
+/// samples every point independently with probability proportional to its
+/// weighted squared distance from the candidates so far, oversampling by
+/// `2 * a` per round. The candidates are then weighted by how many points are
+/// nearest to them and reduced to `a` with k-means++
+///
+/// # Arguments
+/// * `points` - The points to pick from
+/// * `weights` - The weight of each point
+/// * `space` - The color space `points` are in
+/// * `metric` - How distances between `points` are measured
+/// * `a` - How many points to pick
+/// * `rng` - The source of randomness for the picks
+pub(crate) fn kmeans_parallel(
+    points: &[Point],
+    weights: &[f64],
+    space: ColorSpace,
+    metric: DistanceMetric,
+    a: usize,
+    rng: &mut dyn RandomSource,
+) -> Vec<usize> {
+    let a = a.min(points.len());
+    let Some(first) = weighted_pick(weights, rng) else {
+        return vec![];
+    };
+    let mut candidates = vec![first];
+    let mut min_d2 = min_squared_distances(points, &candidates, space, metric);
+    let oversample = 2.0 * a as f64;
+
+    for _ in 0..PARALLEL_ROUNDS {
+        let cost: f64 = min_d2.iter().zip(weights).map(|(d2, w)| d2 * w).sum();
+        if cost <= 0.0 {
+            break;
+        }
+
+        let sampled: Vec<usize> = (0..points.len())
+            .filter(|&i| rng.next_unit() < oversample * min_d2[i] * weights[i] / cost)
+            .collect();
+        for &i in &sampled {
+            update_min_squared_distances(points, &points[i], space, metric, &mut min_d2);
+        }
+        candidates.extend(sampled);
+    }
+
+    if candidates.len() <= a {
+        return kmeans_plus_plus(points, weights, space, metric, a, candidates, rng);
+    }
+
+    let candidate_points: Vec<Point> = candidates.iter().map(|&i| points[i]).collect();
+    let mut candidate_weights = vec![0.0; candidates.len()];
+    for (point, weight) in points.iter().zip(weights) {
+        let (nearest, _) = metric.nearest(space, point, &candidate_points);
+        candidate_weights[nearest] += weight;
+    }
+
+    kmeans_plus_plus(
+        &candidate_points,
+        &candidate_weights,
+        space,
+        metric,
+        a,
+        vec![],
+        rng,
+    )
+    .into_iter()
+    .map(|i| candidates[i])
+    .collect()
+}
+
+/// Pick an index with probability proportional to its score, `None` if every
+/// score is 0
+fn weighted_pick(scores: &[f64], rng: &mut dyn RandomSource) -> Option<usize> {
+    let total: f64 = scores.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut target = rng.next_unit() * total;
+    for (i, score) in scores.iter().enumerate() {
+        if *score > 0.0 {
+            if target < *score {
+                return Some(i);
+            }
+            target -= score;
+        }
+    }
+
+    scores.iter().rposition(|score| *score > 0.0)
+}
+
+/// The squared distance from each point to its nearest pick, infinite when
+/// nothing is picked yet
+fn min_squared_distances(
+    points: &[Point],
+    picked: &[usize],
+    space: ColorSpace,
+    metric: DistanceMetric,
+) -> Vec<f64> {
+    let mut min_d2 = vec![f64::INFINITY; points.len()];
+    for &p in picked {
+        update_min_squared_distances(points, &points[p], space, metric, &mut min_d2);
+    }
+    min_d2
+}
+
+fn update_min_squared_distances(
+    points: &[Point],
+    pick: &Point,
+    space: ColorSpace,
+    metric: DistanceMetric,
+    min_d2: &mut [f64],
+) {
+    for (point, d2) in points.iter().zip(min_d2.iter_mut()) {
+        *d2 = d2.min(metric.squared_distance(space, point, pick));
+    }
+}
@@ -0,0 +1,95 @@
+use crate::color_space::ColorSpace;
+use crate::Color;
+
+const fn rgb(hex: u32) -> Color {
+    Color {
+        r: (hex >> 16) as i32,
+        g: ((hex >> 8) & 0xFF) as i32,
+        b: (hex & 0xFF) as i32,
+    }
+}
+
+/// Kenneth Kelly's 22 colors of maximum contrast, "Twenty-two colors for
+/// maximum contrast" (1965)
+const KELLY_COLORS: [Color; 22] = [
+    rgb(0xF2F3F4),
+    rgb(0x222222),
+    rgb(0xF3C300),
+    rgb(0x875692),
+    rgb(0xF38400),
+    rgb(0xA1CAF1),
+    rgb(0xBE0032),
+    rgb(0xC2B280),
+    rgb(0x848482),
+    rgb(0x008856),
+    rgb(0xE68FAC),
+    rgb(0x0067A5),
+    rgb(0xF99379),
+    rgb(0x604E97),
+    rgb(0xF6A600),
+    rgb(0xB3446C),
+    rgb(0xDCD300),
+    rgb(0x882D17),
+    rgb(0x8DB600),
+    rgb(0x654522),
+    rgb(0xE25822),
+    rgb(0x2B3D26),
+];
+
+/// A display color for each palette color, picked from Kelly's colors to be
+/// as far apart as possible in Lab. The Kelly color nearest the first palette
+/// color is picked first and each next one is the furthest from those picked,
+/// then the picks are matched to the palette colors nearest pairs first.
+/// Palette colors past the 22nd keep their own color
+///
+/// # Arguments
+/// * `palette` - The palette to find display colors for
+pub(crate) fn display_colors(palette: &[Color]) -> Vec<Color> {
+    let space = ColorSpace::Lch;
+    let distance = |a: &Color, b: &Color| space.distance(&space.to_point(a), &space.to_point(b));
+    let Some(first) = palette.first() else {
+        return vec![];
+    };
+
+    let mut picked: Vec<&Color> = vec![];
+    while picked.len() < palette.len().min(KELLY_COLORS.len()) {
+        let spacing = |c: &Color| {
+            picked
+                .iter()
+                .map(|p| distance(p, c))
+                .fold(f32::INFINITY, f32::min)
+        };
+        let next = KELLY_COLORS
+            .iter()
+            .filter(|c| !picked.contains(c))
+            .min_by(|a, b| {
+                if picked.is_empty() {
+                    distance(first, a).total_cmp(&distance(first, b))
+                } else {
+                    spacing(b).total_cmp(&spacing(a))
+                }
+            })
+            .unwrap();
+        picked.push(next);
+    }
+
+    // the closest pair of a palette color and a pick are matched first, so
+    // a color is only given a poor match when its better ones are taken
+    let mut display: Vec<Color> = palette.to_vec();
+    let mut unmatched: Vec<usize> = (0..picked.len()).collect();
+    while !picked.is_empty() {
+        let (u, p) = unmatched
+            .iter()
+            .enumerate()
+            .flat_map(|(u, &i)| (0..picked.len()).map(move |p| (u, p, i)))
+            .min_by(|a, b| {
+                distance(&palette[a.2], picked[a.1])
+                    .total_cmp(&distance(&palette[b.2], picked[b.1]))
+            })
+            .map(|(u, p, _)| (u, p))
+            .unwrap();
+        display[unmatched.remove(u)] = picked.remove(p).clone();
+    }
+
+    display
+}
@@ -0,0 +1,127 @@
+use crate::color_space::{ColorSpace, Point};
+
+/// The state of a k-medoids clustering, the medoids along with each point's
+/// nearest and second nearest medoid distances
+pub(crate) struct Medoids<'a> {
+    points: &'a [Point],
+    weights: &'a [f64],
+    space: ColorSpace,
+    /// Indices into `points` of the current medoids
+    pub medoids: Vec<usize>,
+    nearest: Vec<usize>,
+    nearest_dist: Vec<f64>,
+    second_dist: Vec<f64>,
+}
+
+impl<'a> Medoids<'a> {
+    /// Start a clustering from the given medoids
+    ///
+    /// # Arguments
+    /// * `points` - The points to cluster
+    /// * `weights` - The weight of each point
+    /// * `space` - The color space `points` are in
+    /// * `medoids` - Indices of the initial medoids, must not be empty
+    pub fn new(
+        points: &'a [Point],
+        weights: &'a [f64],
+        space: ColorSpace,
+        medoids: Vec<usize>,
+    ) -> Medoids<'a> {
+        let mut state = Medoids {
+            points,
+            weights,
+            space,
+            medoids,
+            nearest: vec![0; points.len()],
+            nearest_dist: vec![0.0; points.len()],
+            second_dist: vec![0.0; points.len()],
+        };
+        state.assign();
+        state
+    }
+
+    /// The objective being minimized, the weighted sum of distances from each
+    /// point to its medoid
+    pub fn cost(&self) -> f32 {
+        self.nearest_dist
+            .iter()
+            .zip(self.weights)
+            .map(|(d, w)| d * w)
+            .sum::<f64>() as f32
+    }
+
+    /// Make the single best swap of a medoid for a non-medoid point, if any
+    /// swap lowers the cost. This is the PAM swap step, evaluating the change
+    /// for every medoid at once for each candidate point (as in FastPAM1) so a
+    /// pass is quadratic in the number of points rather than cubic. Returns
+    /// whether a swap was made
+    pub fn swap(&mut self) -> bool {
+        let k = self.medoids.len();
+        let mut best = (0.0, 0, 0);
+
+        for candidate in 0..self.points.len() {
+            if self.medoids.contains(&candidate) {
+                continue;
+            }
+
+            let mut shared = 0.0;
+            let mut removal = vec![0.0; k];
+
+            for (j, point) in self.points.iter().enumerate() {
+                let dist = self.space.distance(&self.points[candidate], point) as f64;
+                let weight = self.weights[j];
+
+                if dist < self.nearest_dist[j] {
+                    // j moves to the candidate whichever medoid is removed
+                    shared += weight * (dist - self.nearest_dist[j]);
+                } else {
+                    // j only moves if its own medoid is removed
+                    removal[self.nearest[j]] +=
+                        weight * (dist.min(self.second_dist[j]) - self.nearest_dist[j]);
+                }
+            }
+
+            for (i, delta) in removal.iter().enumerate() {
+                if shared + delta < best.0 {
+                    best = (shared + delta, i, candidate);
+                }
+            }
+        }
+
+        // ignore float noise so equal cost swaps can't cycle
+        if best.0 >= -1e-6 {
+            return false;
+        }
+
+        self.medoids[best.1] = best.2;
+        self.assign();
+        true
+    }
+
+    /// Index into `medoids` of each point's nearest medoid
+    pub fn assignments(&self) -> &[usize] {
+        &self.nearest
+    }
+
+    fn assign(&mut self) {
+        let medoid_points: Vec<Point> = self.medoids.iter().map(|&m| self.points[m]).collect();
+
+        for (j, point) in self.points.iter().enumerate() {
+            let (mut first, mut second) = ((0, f64::INFINITY), f64::INFINITY);
+
+            for (i, medoid) in medoid_points.iter().enumerate() {
+                let dist = self.space.distance(point, medoid) as f64;
+                if dist < first.1 {
+                    second = first.1;
+                    first = (i, dist);
+                } else if dist < second {
+                    second = dist;
+                }
+            }
+
+            self.nearest[j] = first.0;
+            self.nearest_dist[j] = first.1;
+            self.second_dist[j] = second;
+        }
+    }
+}
@@ -1,13 +1,30 @@
+mod color_space;
+mod fuzzy;
+mod hilbert;
+mod init;
+mod kelly;
+mod kmedoids;
+mod png;
+mod random;
+mod regions;
 mod utils;
 
+use color_space::Point;
+pub use color_space::{ColorSpace, DistanceMetric};
 use gloo_utils::format::JsValueSerdeExt;
-use itertools::Itertools;
-use rand::seq::IteratorRandom;
+pub use init::InitMethod;
+pub use random::RandomSource;
 use serde_derive::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
 use tsify::Tsify;
-use utils::set_panic_hook;
+use utils::{now_ms, set_panic_hook};
 use wasm_bindgen::prelude::*;
-use web_sys::CanvasRenderingContext2d;
+#[cfg(feature = "web")]
+use wasm_bindgen::Clamped;
+#[cfg(feature = "web")]
+use web_sys::{CanvasRenderingContext2d, ImageData};
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
@@ -15,242 +32,4637 @@ use web_sys::CanvasRenderingContext2d;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+/// The largest value of a color channel, every channel is from `0` to this
+pub const CHANNEL_MAX: i32 = 255;
+
+/// How many seeded fixed `k` runs are remembered for exact repeats
+const RUN_CACHE_SIZE: usize = 8;
+
+/// The most iterations a run makes when `max_iterations` isn't configured
+const DEFAULT_MAX_ITERATIONS: usize = 11;
+
+/// The quantize factor used when neither `quantize_fact` nor
+/// `target_distinct` is configured, leaving the colors as they are
+const DEFAULT_QUANTIZE_FACT: i32 = 1;
+
+/// How many consecutive `k` values must improve the WCSS by less than
+/// `plateau_threshold` before a derived run stops early
+const PLATEAU_STEPS: usize = 2;
+
 /// Represents an RGB color
-#[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize, Tsify)]
+#[derive(Clone, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Tsify)]
 pub struct Color {
-    r: i32,
-    g: i32,
-    b: i32,
+    pub r: i32,
+    pub g: i32,
+    pub b: i32,
+}
+
+impl Color {
+    /// The WCAG relative luminance of the color, from `0.0` for black to
+    /// `1.0` for white
+    pub fn relative_luminance(&self) -> f32 {
+        let [r, g, b] = [self.r, self.g, self.b].map(|c| color_space::to_linear(c as f32));
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+
+    /// Linearly interpolate between two colors, `t` is clamped to `0.0..=1.0`
+    /// where `0.0` gives `a` and `1.0` gives `b`
+    ///
+    /// # Arguments
+    /// * `a` - the color to blend from
+    /// * `b` - the color to blend to
+    /// * `t` - how far to blend from `a` to `b`
+    pub fn blend(a: &Color, b: &Color, t: f32) -> Color {
+        Color::blend_channels(a, b, t, |c| c, |c| c)
+    }
+
+    /// Interpolate between two colors as `blend` does but in linear light, so
+    /// the luminance changes evenly and midpoints don't look muddy
+    ///
+    /// # Arguments
+    /// * `a` - the color to blend from
+    /// * `b` - the color to blend to
+    /// * `t` - how far to blend from `a` to `b`
+    pub fn blend_linear(a: &Color, b: &Color, t: f32) -> Color {
+        Color::blend_channels(a, b, t, color_space::to_linear, color_space::from_linear)
+    }
+
+    /// The color as a zero-padded uppercase hex string such as `#0A1B2C`
+    pub fn to_hex(&self) -> String {
+        format!("#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+
+    /// The distance to another color in the given color space, the same
+    /// distance clustering in that space uses with the default
+    /// `DistanceMetric::Euclidean`
+    ///
+    /// # Arguments
+    /// * `other` - the color to measure to
+    /// * `space` - the color space to measure in
+    pub fn distance_to(&self, other: &Color, space: ColorSpace) -> f32 {
+        self.distance_with(other, space, DistanceMetric::Euclidean)
+    }
+
+    /// The squared distance to another color in the given color space, as
+    /// the WCSS sums it with the default `DistanceMetric::Euclidean`. Exact
+    /// for RGB
+    ///
+    /// # Arguments
+    /// * `other` - the color to measure to
+    /// * `space` - the color space to measure in
+    pub fn squared_distance_to(&self, other: &Color, space: ColorSpace) -> f32 {
+        self.squared_distance_with(other, space, DistanceMetric::Euclidean)
+    }
+
+    /// The distance to another color in the given color space measured with
+    /// the given metric, the same distance a run configured with both uses
+    ///
+    /// # Arguments
+    /// * `other` - the color to measure to
+    /// * `space` - the color space to measure in
+    /// * `metric` - how distances are measured in `space`
+    pub fn distance_with(&self, other: &Color, space: ColorSpace, metric: DistanceMetric) -> f32 {
+        metric.distance(space, &space.to_point(self), &space.to_point(other))
+    }
+
+    /// The squared distance to another color in the given color space
+    /// measured with the given metric, as the WCSS of a run configured with
+    /// both sums it
+    ///
+    /// # Arguments
+    /// * `other` - the color to measure to
+    /// * `space` - the color space to measure in
+    /// * `metric` - how distances are measured in `space`
+    pub fn squared_distance_with(
+        &self,
+        other: &Color,
+        space: ColorSpace,
+        metric: DistanceMetric,
+    ) -> f32 {
+        metric.squared_distance(space, &space.to_point(self), &space.to_point(other)) as f32
+    }
+
+    /// The exact squared euclidean distance to another color, used wherever
+    /// the nearest of several colors is chosen
+    ///
+    /// # Arguments
+    /// * `other` - the color to measure to
+    fn squared_dist(&self, other: &Color) -> i32 {
+        (self.r - other.r).pow(2) + (self.g - other.g).pow(2) + (self.b - other.b).pow(2)
+    }
+
+    fn blend_channels(
+        a: &Color,
+        b: &Color,
+        t: f32,
+        decode: impl Fn(f32) -> f32,
+        encode: impl Fn(f32) -> f32,
+    ) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let channel = |p: i32, q: i32| {
+            let (p, q) = (decode(p as f32), decode(q as f32));
+            encode(p + (q - p) * t).round().clamp(0.0, 255.0) as i32
+        };
+
+        Color {
+            r: channel(a.r, b.r),
+            g: channel(a.g, b.g),
+            b: channel(a.b, b.b),
+        }
+    }
+
+    /// Quantize each channel down to a multiple of `fact`
+    ///
+    /// # Arguments
+    /// * `fact` - the quantization factor, `1` leaves the color unchanged
+    fn quantized(&self, fact: i32) -> Color {
+        Color {
+            r: self.r / fact * fact,
+            g: self.g / fact * fact,
+            b: self.b / fact * fact,
+        }
+    }
+
+    /// Divide premultiplied channels back out by their alpha, rounding to the
+    /// nearest whole channel. A fully transparent color is left as it is
+    ///
+    /// # Arguments
+    /// * `alpha` - the alpha the channels were multiplied by
+    fn unpremultiplied(&self, alpha: u8) -> Color {
+        if alpha == 0 {
+            return self.clone();
+        }
+        let alpha = alpha as i32;
+        let channel = |c: i32| ((c * 255 + alpha / 2) / alpha).min(255);
+        Color {
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+        }
+    }
+
+    /// Scale the HSV saturation of the color, keeping its hue and value. The
+    /// saturation is limited to `1.0` and greys stay grey
+    ///
+    /// # Arguments
+    /// * `scale` - what to multiply the saturation by
+    fn saturated(&self, scale: f32) -> Color {
+        let [value, chroma, hue] = ColorSpace::Hsv.to_point(self);
+        ColorSpace::Hsv.to_color([value, chroma * scale, hue])
+    }
+
+    /// Whether the color is a grey, its highest and lowest channels differing
+    /// by no more than `tolerance`
+    ///
+    /// # Arguments
+    /// * `tolerance` - the largest channel spread of a grey
+    fn is_grayscale(&self, tolerance: u8) -> bool {
+        let (max, min) = (
+            self.r.max(self.g).max(self.b),
+            self.r.min(self.g).min(self.b),
+        );
+        max - min <= tolerance as i32
+    }
+
+    /// Raise each channel, as a fraction of 255, to the power `gamma`
+    ///
+    /// # Arguments
+    /// * `gamma` - the exponent, `1.0` leaves the color unchanged
+    fn with_gamma(&self, gamma: f32) -> Color {
+        let channel = |c: i32| ((c as f32 / 255.0).powf(gamma) * 255.0).round() as i32;
+        Color {
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+        }
+    }
+
+    /// Whether every channel is in the supported range, from `0` to
+    /// `CHANNEL_MAX`
+    pub fn in_range(&self) -> bool {
+        [self.r, self.g, self.b]
+            .iter()
+            .all(|v| (0..=CHANNEL_MAX).contains(v))
+    }
+
+    /// The 216 web-safe colors, every combination of the channel values `0`,
+    /// `51`, `102`, `153`, `204` and `255`
+    pub fn web_safe() -> Vec<Color> {
+        const STEPS: [i32; 6] = [0, 51, 102, 153, 204, 255];
+        STEPS
+            .iter()
+            .flat_map(|&r| {
+                STEPS
+                    .iter()
+                    .flat_map(move |&g| STEPS.iter().map(move |&b| Color { r, g, b }))
+            })
+            .collect()
+    }
+
+    /// The WCAG contrast ratio between two colors, from `1.0` for identical
+    /// luminance to `21.0` for black against white
+    ///
+    /// # Arguments
+    /// * `other` - the color to compare against
+    pub fn contrast_ratio(&self, other: &Color) -> f32 {
+        let (a, b) = (self.relative_luminance(), other.relative_luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
 }
 
 /// Each 'run' of the cluster calculation produces a result
 /// containing the `k` size used, the vector of clusters found
-/// and the within-cluster sum of squares (WCSS)
+/// and the within-cluster sum of squares (WCSS), measured in the
+/// `Config.color_space` the clusters were formed in
 #[derive(Clone, Serialize, Deserialize, Tsify)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
 pub struct RunResult {
-    ks: usize,
-    clusters: Vec<Color>,
-    wcss: f32,
+    pub ks: usize,
+    pub clusters: Vec<Color>,
+    pub wcss: f32,
+    /// How many distinct colors there are in `clusters`, below `ks` when
+    /// clusters have collapsed onto the same color
+    pub distinct: usize,
+    /// How many pixels are nearest each of `clusters`, in the same order. Pixels
+    /// count as set by `Config.alpha_mode`, so transparent pixels can be left
+    /// out of these proportions. When `Config.coherence_weight` is set this
+    /// includes the extra weight given to coherent pixels
+    pub counts: Vec<usize>,
+    /// Each cluster's share of the variance the palette explains, in the same
+    /// order as `clusters`. The WCSS of a single cluster at the mean of every
+    /// color, less the palette's WCSS, is split between the clusters by how
+    /// much nearer each brings its own colors, so the shares sum to `1.0`.
+    /// Every share is `0.0` when the palette explains nothing, such as for a
+    /// single cluster or an image of one color
+    pub variance_explained: Vec<f32>,
+    /// How many iterations the run took before converging or reaching the
+    /// iteration limit
+    pub iterations: usize,
+    /// The WCSS after each k-means iteration, for plotting how quickly the
+    /// run converged. Any later changes to the clusters, such as
+    /// `snap_only_final` or `collision` reseeding, are only in `wcss`. Empty
+    /// for the other algorithms
+    pub convergence: Vec<f32>,
+    /// The unrounded mean of the colors nearest each of `clusters` as RGB
+    /// channels, in the same order. These are the means before any snapping
+    /// or reseeding, so with `snap_only_final` they show how far each cluster
+    /// moved to reach a real color. Only present when `Config.float_centroids`
+    /// is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub float_centroids: Option<Vec<[f32; 3]>>,
+    /// The state after each iteration, only present when `Config.trace` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Vec<IterationTrace>>,
+    /// Every position of the clusters through the run, the initial clusters
+    /// followed by the clusters after each iteration and ending with the final
+    /// `clusters`. Only present when `Config.centroid_history` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub centroid_history: Option<Vec<Vec<Color>>>,
+    /// How long the stages of the run took, only present when
+    /// `Config.measure` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<RunTiming>,
+    /// Anything about the run that's likely not what the caller intended,
+    /// left out when there's nothing to report
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<RunWarning>,
 }
 
-/// Represents the instance of the module containing the current images
-/// pixel Colors and the last set of RunResults
-#[wasm_bindgen]
-#[derive(Serialize)]
-pub struct ImageKmeans {
-    colors: Vec<Color>,
-    initial_ks: Vec<Color>,
-    results: Vec<RunResult>,
+/// Palette colors with each channel normalized to `0.0..=1.0`
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct NormalizedPalette(pub Vec<[f32; 3]>);
+
+/// A list of colors, such as a gradient built from the palette
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ColorRamp(pub Vec<Color>);
+
+/// A palette alongside a display color for each of its colors, aligned by
+/// index, picked to be easy to tell apart in a legend
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct DisplayPalette {
+    /// The true palette colors
+    pub palette: Vec<Color>,
+    /// The display color standing in for each palette color
+    pub display: Vec<Color>,
 }
 
-#[wasm_bindgen]
-impl ImageKmeans {
-    /// Creates an instance of the ImageKmeans. This is also decorated as
-    /// the JS constructor so will be run directly as a call to
+/// A palette color along with how much of the image it covers
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct PaletteEntry {
+    /// The position of the color in the result's clusters
+    pub index: usize,
+    pub color: Color,
+    /// The color as a hex string such as `#0A1B2C`
+    pub hex: String,
+    /// How many pixels are nearest the color
+    pub count: usize,
+    /// The fraction of all pixels nearest the color
+    pub fraction: f32,
+}
+
+/// A list of `PaletteEntry`s, one per cluster
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct PaletteEntries(pub Vec<PaletteEntry>);
+
+/// A broad classification of an image's colors by how many hues it uses
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum ColorTheme {
+    /// Greys, or shades of a single hue
+    Monochrome,
+    /// Shades of two hues
+    Duotone,
+    /// Three or more hues
+    Polychrome,
+}
+
+/// Scalar descriptors of a palette, each averaged over its colors weighted by
+/// how many pixels are nearest them
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct PaletteSummary {
+    /// The fraction of the pixels nearest a warm color, one that isn't grey
+    /// with an LCh hue from magenta-red through orange to yellow
+    pub warmth: f32,
+    /// The mean HSV saturation, from `0.0` to `1.0`
+    pub saturation: f32,
+    /// The mean LCh lightness scaled to `0.0..=1.0`
+    pub lightness: f32,
+}
+
+/// Pairs of palette indices `[i, j]` with the WCAG contrast ratio between them
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ContrastPairs(pub Vec<((usize, usize), f32)>);
+
+/// The WCSS for each `k` of the latest runs, ready for plotting an elbow chart
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ElbowCurve {
+    /// `[k, wcss]` pairs in the order the runs were made
+    pub points: Vec<(usize, f32)>,
+    /// Index into `points` of the result that was returned
+    pub selected: usize,
+}
+
+/// Why `with_derived_k_number` chose the `k` it did, each run's WCSS is
+/// plotted against its `k` and the run is chosen from that curve as the
+/// configured `k_selection` says
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SelectionDetail {
+    /// The distance of each run from the line, or with `KSelection::Kneedle`
+    /// its value on the difference curve, in the order the runs were made
+    pub distances: Vec<f32>,
+    /// The `k` of the chosen run
+    pub chosen_k: usize,
+    /// The distance, or difference, of the chosen run
+    pub chosen_distance: f32,
+}
+
+/// A snapshot of a single iteration of a run, collected when tracing
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+pub struct IterationTrace {
+    pub iteration: usize,
+    pub clusters: Vec<Color>,
+    pub wcss: f32,
+    /// The mean distance the clusters moved during this iteration
+    pub shift: f32,
+    /// How many working colors changed cluster during this iteration, on the
+    /// first iteration this is all of them
+    pub reassigned: usize,
+}
+
+/// Something about a run that's allowed but likely not what was intended
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum RunWarning {
+    /// `top_num` kept fewer colors than the `k` asked for, so the run has
+    /// fewer clusters than `k`
+    TopNumBelowK { top_num: usize, k: usize },
+}
+
+/// Wall-clock timings of a run in milliseconds, collected when measuring.
+/// The runs of a derived run or a batch share their working colors, and
+/// their initial clusters unless `grayscale_split` is set, so they report the
+/// same time for building and picking them
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Tsify)]
+pub struct RunTiming {
+    /// Building the working colors, quantizing and limiting them
+    pub working_colors_ms: f64,
+    /// Picking the initial clusters
+    pub init_ms: f64,
+    /// The iterations of the algorithm
+    pub iterations_ms: f64,
+}
+
+/// A list of `RunResult`s returned from methods performing several runs
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct RunResults(pub Vec<RunResult>);
+
+/// Errors returned from the module, these are serialized to JS as objects
+/// tagged by `type` so TypeScript can discriminate on them
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "type")]
+pub enum KmeansError {
+    /// The supplied image contained no pixels
+    EmptyImage,
+    /// There are no colors available to cluster
+    NoColors,
+    /// A supplied argument or `Config` option is invalid
+    InvalidConfig { reason: String },
+    /// The image dimensions or pixel data length are invalid
+    InvalidDimensions,
+    /// The method needs the result of a run but there hasn't been one yet
+    NoRun,
+    /// The run was cancelled before it finished
+    Cancelled,
+}
+
+impl fmt::Display for KmeansError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KmeansError::EmptyImage => write!(f, "the image contains no pixels"),
+            KmeansError::NoColors => write!(f, "there are no colors to cluster"),
+            KmeansError::InvalidConfig { reason } => write!(f, "invalid config: {}", reason),
+            KmeansError::InvalidDimensions => write!(f, "invalid image dimensions"),
+            KmeansError::NoRun => write!(f, "there hasn't been a run yet"),
+            KmeansError::Cancelled => write!(f, "the run was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for KmeansError {}
+
+impl From<KmeansError> for JsValue {
+    fn from(err: KmeansError) -> JsValue {
+        err.into_js().unwrap_throw().into()
+    }
+}
+
+/// The clustering algorithm used for a run
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum Algorithm {
+    /// Lloyd's k-means, clusters are the mean of their members
+    #[default]
+    KMeans,
+    /// k-medoids with PAM swaps, clusters are always a color in the image
+    /// chosen to minimize the sum of distances to the cluster's members. More
+    /// expensive than k-means, quadratic in the number of working colors, so
+    /// best used along with `quantize_fact` or `top_num`. The `wcss` of the
+    /// results is this sum of distances
+    KMedoids,
+    /// A fast approximation for previews. The working colors are sorted along
+    /// a Hilbert curve through the RGB cube and split into `k` runs at the
+    /// largest jumps along it, each cluster is the mean of its run. There are
+    /// no iterations so `init` has no effect
+    Hilbert1D,
+    /// Fuzzy c-means, every color belongs to every cluster with a membership
+    /// from its relative distance to them and clusters are the mean of all
+    /// the colors weighted by their membership raised to `m`. Gives smoother
+    /// palettes on gradients. The `wcss` of the results is the fuzzy objective,
+    /// the squared distances weighted in the same way
+    FuzzyCMeans {
+        /// The fuzziness exponent, above `1.0`. Near `1.0` behaves like
+        /// k-means, `2.0` is the usual choice
+        m: f32,
+    },
+}
+
+/// How k-means computes each cluster from the colors assigned to it
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum CentroidMode {
+    /// The weighted mean of the colors
+    #[default]
+    Mean,
+    /// The weighted geometric median of the colors, the color with the least
+    /// total distance to them. Far less pulled by a few stray colors, such as
+    /// anti-aliased edges, than the mean
+    GeometricMedian,
+}
+
+/// What each color of the image is counted by before clustering
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum RegionWeighting {
+    /// How many pixels have the color
+    #[default]
+    Pixels,
+    /// The size in pixels of the color's largest connected region, so one
+    /// solid blob outweighs the same number of scattered pixels
+    LargestRegion,
+    /// How many separate connected regions have the color
+    RegionCount,
+}
+
+/// What to do when several clusters end up as the same color
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum CollisionMode {
+    /// Leave the clusters as they are, `RunResult.distinct` shows how many
+    /// distinct colors remain
+    #[default]
+    Allow,
+    /// Move each repeated cluster to the working color furthest from every
+    /// cluster, so the palette has `k` distinct colors wherever the image has
+    /// enough colors
+    Reseed,
+}
+
+/// When colors are quantized relative to picking the most frequent ones with
+/// `top_num` or `top_percent`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum QuantizeStage {
+    /// Quantize every pixel first then keep the most frequent quantized colors
+    #[default]
+    BeforeTopN,
+    /// Keep the most frequent original colors then quantize only those,
+    /// merging any that quantize to the same color
+    AfterTopN,
+}
+
+/// How each pixel's alpha affects how much it counts towards the colors
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum AlphaMode {
+    /// Alpha is ignored, every pixel counts once
+    #[default]
+    Ignore,
+    /// Fully transparent pixels don't count, every other pixel counts once
+    SkipTransparent,
+    /// Each pixel counts in proportion to its alpha, so `RunResult.counts`
+    /// are in 255ths of a pixel
+    Weighted,
+    /// The pixel data has premultiplied alpha, its channels already scaled by
+    /// alpha. Each color is divided back out by its alpha before counting,
+    /// fully transparent pixels don't count and every other pixel counts once
+    Premultiplied,
+}
+
+impl AlphaMode {
+    /// How much a pixel with the given alpha counts towards its color, `0`
+    /// when it doesn't count at all
     ///
-    /// ```js
-    /// const kmeans = new ImageKmeans(ctx, height, width);
-    /// ```
+    /// # Arguments
+    /// * `alpha` - the pixel's alpha
+    fn pixel_count(self, alpha: u8) -> usize {
+        match self {
+            AlphaMode::Ignore => 1,
+            AlphaMode::SkipTransparent | AlphaMode::Premultiplied => (alpha > 0) as usize,
+            AlphaMode::Weighted => alpha as usize,
+        }
+    }
+
+    /// The color a pixel with the given alpha counts as
     ///
     /// # Arguments
+    /// * `color` - the pixel's color as given
+    /// * `alpha` - the pixel's alpha
+    fn pixel_color(self, color: &Color, alpha: u8) -> Color {
+        match self {
+            AlphaMode::Premultiplied => color.unpremultiplied(alpha),
+            _ => color.clone(),
+        }
+    }
+}
+
+/// The order the clusters of a `RunResult` are given in
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum SortOrder {
+    /// The order the run left them in, any locked colors first
+    #[default]
+    Unsorted,
+    /// By the first pixel of the image, scanning from the top left, that is
+    /// nearest each cluster. Clusters no pixel is nearest to come last
+    FirstAppearance,
+}
+
+/// How `with_derived_k_number` chooses `k` from the WCSS of its runs
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Tsify)]
+pub enum KSelection {
+    /// The run furthest from the line joining the ends of the WCSS curve,
+    /// see also `Config.normalize_elbow`
+    #[default]
+    Elbow,
+    /// The kneedle algorithm. With `k` and the WCSS normalized, the first
+    /// local maximum of the curve's difference from the diagonal that is
+    /// followed by a fall of more than `sensitivity` steps of `k` is the knee.
+    /// Unlike the elbow this finds no knee in a curve falling almost linearly,
+    /// `k = 1` is chosen then
+    Kneedle {
+        /// How far the difference must fall after a maximum, in steps of `k`.
+        /// `1.0` is the usual choice, higher values are more conservative
+        sensitivity: f32,
+    },
+}
+
+/// How `Config.grayscale_split` divides the colors and `k` between the grey
+/// and chromatic runs
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Tsify)]
+pub struct GrayscaleSplit {
+    /// The largest difference between a color's highest and lowest channels
+    /// for it to count as grey
+    pub tolerance: u8,
+    /// The fraction of `k` given to the greys, from `0.0` to `1.0`. It's
+    /// rounded so that when there are both greys and chromatic colors each
+    /// gets at least one cluster, if `k` allows
+    pub grayscale_fraction: f32,
+}
+
+/// Options controlling how a clustering run is performed. Every field is
+/// optional from JS, anything omitted takes its default value. The defaults
+/// are those of `Config::default()`, documented on each field, and JS can get
+/// them as a whole from `default_config()`
+#[derive(Clone, Default, Deserialize, PartialEq, Serialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(default)]
+pub struct Config {
+    /// Snap each final centroid to the nearest color that actually appears
+    /// in the image. The iterations themselves always use the true mean so
+    /// convergence is unaffected, the snap only happens once on the output.
+    /// Nearness is measured in `color_space`, so clusters formed in HSV or LCh
+    /// are reported as the real color closest to them in that space
     ///
-    /// * `ctx` - The canvas 2d rendering context containing the image
-    /// * `width` - The width of the rendered image
-    /// * `height` - the height of the rendered image
-    #[wasm_bindgen(constructor)]
-    pub fn new(ctx: &CanvasRenderingContext2d, width: u32, height: u32) -> ImageKmeans {
-        set_panic_hook();
-        let image_data = ctx
-            .get_image_data(0.0, 0.0, width as f64, height as f64)
-            .unwrap();
-        let color_data = image_data.data();
-        let mut pixels: Vec<Color> = vec![];
-
-        for i in (0..color_data.len()).step_by(4) {
-            pixels.push(Color {
-                r: color_data[i] as i32,
-                g: color_data[i + 1] as i32,
-                b: color_data[i + 2] as i32,
-            });
+    /// Defaults to `false`
+    pub snap_only_final: bool,
+    /// Extra weight a pixel adds to its color's count for each of its four
+    /// neighbours sharing the same color, favouring colors that form
+    /// contiguous regions over scattered noise. `0` counts pixels plainly
+    ///
+    /// Defaults to `0`
+    pub coherence_weight: usize,
+    /// Seed for picking the initial clusters, runs with the same seed and
+    /// config on the same image give the same result
+    ///
+    /// Unseeded by default, using the instance's random source
+    pub seed: Option<u64>,
+    /// Only cluster this many of the most frequent colors in the image
+    ///
+    /// Unset by default, clustering every color
+    pub top_num: Option<usize>,
+    /// Only cluster this fraction (`0.0` exclusive to `1.0`) of the most
+    /// frequent distinct colors in the image. Can't be combined with `top_num`
+    ///
+    /// Unset by default
+    pub top_percent: Option<f32>,
+    /// Collect an `IterationTrace` for every iteration into `RunResult.trace`
+    ///
+    /// Defaults to `false`
+    pub trace: bool,
+    /// Replace the final clusters with real image colors that are all at
+    /// least this distance apart, where the image has enough colors to allow
+    /// it. Each cluster takes the nearest unused color far enough from those
+    /// already picked. Distances are measured in `color_space` with
+    /// `distance_metric`, so in `ColorSpace::Lch` this is a CIE76 delta-E and
+    /// in `ColorSpace::Rgb` it's in channel values
+    ///
+    /// Unset by default
+    pub min_separation: Option<f32>,
+    /// The color space clusters are formed in, the results are always sRGB
+    ///
+    /// Defaults to `ColorSpace::Rgb`
+    pub color_space: ColorSpace,
+    /// How the initial clusters are picked
+    ///
+    /// Defaults to `InitMethod::Random`
+    pub init: InitMethod,
+    /// Merge colors within this CIE76 delta-E of a more frequent color into it
+    /// before clustering, summing their counts. Reduces the number of colors
+    /// to cluster while barely changing the palette
+    ///
+    /// Unset by default
+    pub merge_delta_e: Option<f32>,
+    /// Quantize each channel down to a multiple of this factor before counting
+    /// colors, merging similar colors to speed up clustering. `1` leaves the
+    /// colors as they are
+    ///
+    /// Unset by default, which is the same as `1`
+    pub quantize_fact: Option<i32>,
+    /// Pick the smallest quantization factor that reduces the image to at
+    /// most this many distinct colors. Can't be combined with `quantize_fact`,
+    /// the factor used is available from `ImageKmeans.quantize_fact`
+    ///
+    /// Unset by default
+    pub target_distinct: Option<usize>,
+    /// The clustering algorithm to use
+    ///
+    /// Defaults to `Algorithm::KMeans`
+    pub algorithm: Algorithm,
+    /// Colors that must appear in the palette. These take the first clusters
+    /// and still attract pixels but are never moved, the remaining clusters
+    /// are fitted around them. There can't be more of them than `k`
+    ///
+    /// Empty by default
+    pub locked_colors: Vec<Color>,
+    /// What to do when clusters collapse onto the same color
+    ///
+    /// Defaults to `CollisionMode::Allow`
+    pub collision: CollisionMode,
+    /// Whether quantizing happens before or after the `top_num` or
+    /// `top_percent` limit is applied
+    ///
+    /// Defaults to `QuantizeStage::BeforeTopN`
+    pub quantize_stage: QuantizeStage,
+    /// Compute each k-means cluster without its outlying colors, the furthest
+    /// colors from a first mean making up this fraction (below `1.0`) of the
+    /// cluster's pixels are left out of the final mean
+    ///
+    /// Unset by default, using the full mean
+    pub trimmed_mean_fraction: Option<f32>,
+    /// Drop clusters nearest to less than this fraction of the pixels once the
+    /// run has converged, folding their pixels into the nearest remaining
+    /// clusters with one more assignment pass. `RunResult.ks` reports how many
+    /// clusters are left, the most populous cluster and locked colors are
+    /// always kept
+    ///
+    /// Unset by default, keeping every cluster
+    pub min_cluster_fraction: Option<f32>,
+    /// How pixel alpha affects the color counts, and so the clusters and
+    /// their `counts`. Only the image the instance was created from has its
+    /// alpha considered, every pixel of an added image counts once
+    ///
+    /// Defaults to `AlphaMode::Ignore`
+    pub alpha_mode: AlphaMode,
+    /// Also keep this many of the most chromatic colors left out by `top_num`
+    /// or `top_percent`, however rare they are, so small but salient accents
+    /// still get clustered. Chroma is measured in CIE LCh
+    ///
+    /// Unset by default, keeping only the most frequent colors
+    pub top_chromatic: Option<usize>,
+    /// Collect every position of the clusters through each run into
+    /// `RunResult.centroid_history`, to animate them converging
+    ///
+    /// Defaults to `false`
+    pub centroid_history: bool,
+    /// Once the run has converged replace the clusters with real image colors
+    /// picked to be as far apart from each other as possible, starting from
+    /// the most frequent color and repeatedly taking the color furthest from
+    /// those picked. Distances are measured in `color_space` with
+    /// `distance_metric`. Trades representativeness for swatches that are
+    /// easy to tell apart, as in a categorical legend. Can't be combined with
+    /// `min_separation`
+    ///
+    /// Defaults to `false`
+    pub maximize_spread: bool,
+    /// The order to give the final clusters, and their `counts`, in
+    ///
+    /// Defaults to `SortOrder::Unsorted`
+    pub sort_order: SortOrder,
+    /// Raise each channel, as a fraction of 255, to this power before
+    /// clustering and invert it on the output clusters, to bring images with
+    /// different gammas to a common one. The channels are rounded back to
+    /// whole numbers so nearby dark or light colors can merge, and `wcss` is
+    /// measured on the adjusted colors
+    ///
+    /// Unset by default, leaving the channels as they are
+    pub apply_gamma: Option<f32>,
+    /// The most iterations a run makes before stopping, whether or not it has
+    /// converged
+    ///
+    /// Unset by default, allowing 11
+    pub max_iterations: Option<usize>,
+    /// The fewest iterations a run makes before it may stop for having
+    /// converged, guarding against stopping on a flat first iteration. Runs
+    /// still stop at `max_iterations`, which must be at least this many
+    ///
+    /// Unset by default, the same as `1`
+    pub min_iterations: Option<usize>,
+    /// Multiply the HSV saturation of every working color by this before
+    /// clustering, limited to full saturation, so muted colors separate
+    /// better. This changes the output colors as well as the grouping, the
+    /// palette is made of the boosted colors
+    ///
+    /// Unset by default, leaving the saturation as it is
+    pub saturation_scale: Option<f32>,
+    /// Normalize the WCSS curve of a derived run before finding its elbow, so
+    /// a huge `k = 1` WCSS doesn't dominate the choice. Each WCSS is taken as
+    /// `ln(1 + wcss)` and both it and `k` are scaled to `0.0..=1.0`. Rescaling
+    /// alone would only scale every distance from the line by the same amount,
+    /// it's the logarithm that compresses the outlying endpoint. The
+    /// `SelectionDetail.distances` are then in these normalized units. Only
+    /// `KSelection::Elbow` uses this, kneedle normalizes the curve itself
+    ///
+    /// Defaults to `false`
+    pub normalize_elbow: bool,
+    /// Split the working colors into greys and chromatic colors and cluster
+    /// each independently with its share of `k`, so near-grey colors don't
+    /// muddy the chromatic clusters. Unless `sort_order` says otherwise the
+    /// grey clusters come first in the result, its `wcss` and `counts` cover
+    /// both runs and its `iterations` are those of the longer run. Can't be
+    /// combined with `locked_colors`, `trace` or `centroid_history`
+    ///
+    /// Unset by default, clustering every color together
+    pub grayscale_split: Option<GrayscaleSplit>,
+    /// How a derived run chooses `k` from the WCSS of its runs
+    ///
+    /// Defaults to `KSelection::Elbow`
+    pub k_selection: KSelection,
+    /// Time the stages of each run, giving every `RunResult` its `timing`. A
+    /// repeated seeded run served from the cache keeps the timings of the run
+    /// it repeats
+    ///
+    /// Defaults to `false`
+    pub measure: bool,
+    /// The standard deviation of a tiny Gaussian jitter added to each working
+    /// color when k-means assigns it to its nearest cluster, in the units of
+    /// `color_space`. Posterized images have many colors exactly as near two
+    /// clusters, which otherwise all go to the earlier cluster, the jitter
+    /// splits them between the two. The jitter is the same on every run, it
+    /// is seeded by `seed` or a fixed seed when that is unset, and it never
+    /// reaches the means or the output
+    ///
+    /// Unset by default, leaving colors as they are
+    pub jitter: Option<f32>,
+    /// Make sure the run ends with exactly `k` distinct colors whenever the
+    /// image has at least `k` distinct colors. After clustering, clusters
+    /// repeating an earlier cluster are reseeded as `CollisionMode::Reseed`
+    /// does, then any cluster no working color is nearest to is moved to the
+    /// unused working color furthest from the others, until every cluster
+    /// has colors of its own. Locked colors are never moved. This can't be
+    /// used with `min_cluster_fraction`, which drops clusters on purpose
+    ///
+    /// Defaults to `false`
+    pub guarantee_k: bool,
+    /// How k-means measures distances when assigning colors to clusters,
+    /// seeding with k-means++ or k-means|| and scoring the WCSS.
+    /// `DistanceMetric::Chebyshev` can only be used with `ColorSpace::Rgb` and
+    /// `Algorithm::KMeans`, clusters still move to the mean of their colors
+    ///
+    /// Defaults to `DistanceMetric::Euclidean`
+    pub distance_metric: DistanceMetric,
+    /// Never cluster more than this many distinct colors. When there are more,
+    /// after any `top_num` limit and quantizing, the least frequent colors are
+    /// folded into the nearest of the kept ones in `color_space`, adding their
+    /// pixels to its count. Unlike `top_num` every pixel is still counted,
+    /// while the cost of a run stays bounded
+    ///
+    /// Unset by default, clustering every color
+    pub max_working_colors: Option<usize>,
+    /// How each k-means cluster is computed from its colors. Can't be used
+    /// with `trimmed_mean_fraction`, which only applies to the mean
+    ///
+    /// Defaults to `CentroidMode::Mean`
+    pub centroid_mode: CentroidMode,
+    /// What each color is counted by. Regions are the 4-connected areas of
+    /// pixels sharing the same quantized color, pixels left out by
+    /// `alpha_mode` or weighted `0` belong to none. A color's weight scales
+    /// with its new count. Images added with `add_rgba` have no layout so
+    /// their colors are always counted by pixels. Can't be used with
+    /// `coherence_weight`
+    ///
+    /// Defaults to `RegionWeighting::Pixels`
+    pub region_weighting: RegionWeighting,
+    /// Also give each result the unrounded means behind its clusters as
+    /// `RunResult.float_centroids`
+    ///
+    /// Defaults to `false`
+    pub float_centroids: bool,
+    /// Stop a derived run's scan of `k` values early once the WCSS improves by
+    /// less than this fraction of the previous WCSS for a couple of `k` values
+    /// in a row. The elbow is then found on the shorter curve, saving the runs
+    /// an image with few distinct colors gains little from
+    ///
+    /// Unset by default, every `k` from 1 to 10 is run
+    pub plateau_threshold: Option<f32>,
+}
+
+impl Config {
+    /// Check the options are valid together, returning a
+    /// `KmeansError::InvalidConfig` describing the first problem found. This
+    /// is called at the start of every run
+    pub fn validate(&self) -> Result<(), KmeansError> {
+        let invalid = |reason: String| Err(KmeansError::InvalidConfig { reason });
+
+        if self.top_num.is_some() && self.top_percent.is_some() {
+            return invalid("top_num and top_percent can't both be set".to_string());
+        }
+        if self.top_num == Some(0) {
+            return invalid("top_num must be at least 1".to_string());
+        }
+        if self.max_working_colors == Some(0) {
+            return invalid("max_working_colors must be at least 1".to_string());
+        }
+        if self.maximize_spread && self.min_separation.is_some() {
+            return invalid("maximize_spread and min_separation can't both be set".to_string());
+        }
+        if let Some(gamma) = self.apply_gamma {
+            if !(gamma > 0.0 && gamma.is_finite()) {
+                return invalid(format!("apply_gamma ({}) must be above 0", gamma));
+            }
+        }
+        if self.top_chromatic == Some(0) {
+            return invalid("top_chromatic must be at least 1".to_string());
+        }
+        if let Some(pct) = self.top_percent {
+            if !(pct > 0.0 && pct <= 1.0) {
+                return invalid(format!(
+                    "top_percent ({}) must be above 0 and at most 1",
+                    pct
+                ));
+            }
+        }
+
+        if self.quantize_fact.is_some() && self.target_distinct.is_some() {
+            return invalid("quantize_fact and target_distinct can't both be set".to_string());
+        }
+        if let Some(fact) = self.quantize_fact {
+            if !(1..=256).contains(&fact) {
+                return invalid(format!("quantize_fact ({}) must be from 1 to 256", fact));
+            }
+        }
+        if self.target_distinct == Some(0) {
+            return invalid("target_distinct must be at least 1".to_string());
+        }
+        if let Some(delta_e) = self.merge_delta_e {
+            if !(delta_e.is_finite() && delta_e >= 0.0) {
+                return invalid(format!("merge_delta_e ({}) must be at least 0", delta_e));
+            }
+        }
+        if let Some(sep) = self.min_separation {
+            if !(sep.is_finite() && sep >= 0.0) {
+                return invalid(format!("min_separation ({}) must be at least 0", sep));
+            }
+        }
+        if let Some(fraction) = self.min_cluster_fraction {
+            if !(0.0..=1.0).contains(&fraction) {
+                return invalid(format!(
+                    "min_cluster_fraction ({}) must be from 0 to 1",
+                    fraction
+                ));
+            }
+        }
+        if let Some(fraction) = self.trimmed_mean_fraction {
+            if !(0.0..1.0).contains(&fraction) {
+                return invalid(format!(
+                    "trimmed_mean_fraction ({}) must be at least 0 and below 1",
+                    fraction
+                ));
+            }
+        }
+        if self.coherence_weight > 0 && self.region_weighting != RegionWeighting::Pixels {
+            return invalid("coherence_weight can't be used with region_weighting".to_string());
+        }
+        if self.trimmed_mean_fraction.is_some() && self.centroid_mode != CentroidMode::Mean {
+            return invalid(
+                "trimmed_mean_fraction can only be used with the Mean centroid_mode".to_string(),
+            );
+        }
+        check_channels(&self.locked_colors, "locked_colors")?;
+        if !self.locked_colors.is_empty() && self.algorithm != Algorithm::KMeans {
+            return invalid("locked_colors can only be used with KMeans".to_string());
+        }
+        if let Algorithm::FuzzyCMeans { m } = self.algorithm {
+            if !(m > 1.0 && m.is_finite()) {
+                return invalid(format!("FuzzyCMeans m ({}) must be above 1", m));
+            }
+        }
+        if let Some(scale) = self.saturation_scale {
+            if !(scale >= 0.0 && scale.is_finite()) {
+                return invalid(format!("saturation_scale ({}) can't be negative", scale));
+            }
+        }
+        if self.max_iterations == Some(0) {
+            return invalid("max_iterations must be at least 1".to_string());
+        }
+        if self.distance_metric == DistanceMetric::Chebyshev
+            && (self.color_space != ColorSpace::Rgb || self.algorithm != Algorithm::KMeans)
+        {
+            return invalid(
+                "Chebyshev distance_metric can only be used with KMeans in Rgb".to_string(),
+            );
+        }
+        if self.guarantee_k && self.min_cluster_fraction.is_some() {
+            return invalid("guarantee_k can't be used with min_cluster_fraction".to_string());
+        }
+        if let Some(jitter) = self.jitter {
+            if !(jitter > 0.0 && jitter.is_finite()) {
+                return invalid(format!("jitter ({}) must be above 0", jitter));
+            }
+        }
+        if let Some(threshold) = self.plateau_threshold {
+            if !(threshold > 0.0 && threshold < 1.0) {
+                return invalid(format!(
+                    "plateau_threshold ({}) must be above 0 and below 1",
+                    threshold
+                ));
+            }
+        }
+        if let KSelection::Kneedle { sensitivity } = self.k_selection {
+            if !(sensitivity >= 0.0 && sensitivity.is_finite()) {
+                return invalid(format!(
+                    "Kneedle sensitivity ({}) can't be negative",
+                    sensitivity
+                ));
+            }
+        }
+        if let Some(split) = self.grayscale_split {
+            let fraction = split.grayscale_fraction;
+            if !(0.0..=1.0).contains(&fraction) {
+                return invalid(format!(
+                    "grayscale_fraction ({}) must be from 0 to 1",
+                    fraction
+                ));
+            }
+            if !self.locked_colors.is_empty() || self.trace || self.centroid_history {
+                return invalid(
+                    "grayscale_split can't be combined with locked_colors, trace or centroid_history"
+                        .to_string(),
+                );
+            }
+        }
+        let (min, max) = self.iteration_limits();
+        if min > max {
+            return invalid(format!(
+                "min_iterations ({}) can't be above max_iterations ({})",
+                min, max
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The fewest iterations before a run may stop for having converged and
+    /// the most it can make, as `(min, max)`
+    fn iteration_limits(&self) -> (usize, usize) {
+        (
+            self.min_iterations.unwrap_or(1),
+            self.max_iterations.unwrap_or(DEFAULT_MAX_ITERATIONS),
+        )
+    }
+
+    /// Check a `k` number can be run with these options, it must be at least
+    /// 1 and leave room for all of the `locked_colors`
+    ///
+    /// # Arguments
+    /// * `k_number` - The number of clusters to be run
+    fn validate_k(&self, k_number: usize) -> Result<(), KmeansError> {
+        if k_number == 0 {
+            return Err(KmeansError::InvalidConfig {
+                reason: "k_number must be at least 1".to_string(),
+            });
+        }
+        if self.locked_colors.len() > k_number {
+            return Err(KmeansError::InvalidConfig {
+                reason: format!(
+                    "{} locked_colors don't fit in k_number ({})",
+                    self.locked_colors.len(),
+                    k_number
+                ),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Add a warning to a run's result for anything in the config that made
+    /// it differ from what was asked for
+    ///
+    /// # Arguments
+    /// * `k_number` - The number of `k` clusters the run was asked for
+    /// * `result` - The result of the run
+    fn add_warnings(&self, k_number: usize, result: &mut RunResult) {
+        if let Some(top_num) = self.top_num {
+            if top_num < k_number && result.ks < k_number {
+                result.warnings.push(RunWarning::TopNumBelowK {
+                    top_num,
+                    k: k_number,
+                });
+            }
+        }
+    }
+}
+
+/// Check some colors supplied from outside have every channel in range,
+/// returning a `KmeansError::InvalidConfig` naming them if not
+///
+/// # Arguments
+/// * `colors` - The colors to check
+/// * `name` - What the colors are called in the error
+fn check_channels(colors: &[Color], name: &str) -> Result<(), KmeansError> {
+    match colors.iter().all(Color::in_range) {
+        true => Ok(()),
+        false => Err(KmeansError::InvalidConfig {
+            reason: format!("{} channels must be from 0 to {}", name, CHANNEL_MAX),
+        }),
+    }
+}
+
+/// The largest value of a color channel, `CHANNEL_MAX`, for JS
+#[wasm_bindgen]
+pub fn channel_max() -> i32 {
+    CHANNEL_MAX
+}
+
+/// The `Config` used when none is given, with every option at its default.
+/// Lets JS start from the defaults and change a single field
+#[wasm_bindgen]
+pub fn default_config() -> Config {
+    Config::default()
+}
+
+/// Chroma below which a color counts as grey when finding hues
+const THEME_MIN_CHROMA: f32 = 10.0;
+/// Hues within this many degrees of each other count as the same hue
+const THEME_HUE_SPREAD: f32 = 30.0;
+/// The smallest fraction of the pixels a cluster needs to count towards the theme
+const THEME_MIN_FRACTION: f32 = 0.05;
+/// LCh hues from this many degrees, around through 0°, count as warm
+const WARM_HUE_START: f32 = 330.0;
+/// LCh hues below this many degrees count as warm, yellow sits near 103°
+const WARM_HUE_END: f32 = 110.0;
+
+/// A summary of an `ImageKmeans` for debugging, leaving out the per-pixel data
+#[derive(Serialize)]
+struct StateSummary<'a> {
+    width: u32,
+    height: u32,
+    sample_rate: f32,
+    distinct_colors: usize,
+    working_colors: usize,
+    quantize_fact: i32,
+    initial_ks: &'a [Color],
+    results: &'a [RunResult],
+    selected_result: usize,
+}
+
+/// A histogram entry, a color with its pixel count and the total weight of
+/// those pixels
+type HistogramEntry = (Color, usize, f64);
+
+/// Order histogram entries most frequent first, ties broken by the color
+fn by_frequency((a, a_count, _): &HistogramEntry, (b, b_count, _): &HistogramEntry) -> Ordering {
+    b_count.cmp(a_count).then_with(|| a.cmp(b))
+}
+
+/// The options a full color histogram depends on
+#[derive(PartialEq)]
+struct HistogramKey {
+    fact: i32,
+    coherence_weight: usize,
+    merge_delta_e: Option<f32>,
+    alpha_mode: AlphaMode,
+    region_weighting: RegionWeighting,
+}
+
+/// The points of a WCSS curve for elbow finding with each WCSS taken as
+/// `ln(1 + wcss)`, and both it and the position along the curve scaled to
+/// `0.0..=1.0`
+///
+/// # Arguments
+/// * `wcss` - The WCSS of each run in order of `k`, must not be empty
+fn normalized_curve(wcss: &[f32]) -> Vec<(f32, f32)> {
+    let logs: Vec<f32> = wcss.iter().map(|w| w.max(0.0).ln_1p()).collect();
+    let (min, max) = logs
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &l| {
+            (lo.min(l), hi.max(l))
+        });
+    let span = if max > min { max - min } else { 1.0 };
+    let last = (wcss.len() - 1).max(1) as f32;
+
+    logs.iter()
+        .enumerate()
+        .map(|(i, l)| (i as f32 / last, (l - min) / span))
+        .collect()
+}
+
+/// The kneedle difference curve of a WCSS curve. With `k` and the WCSS both
+/// scaled to `0.0..=1.0` the falling WCSS is flipped to rise, and each point's
+/// difference is how far that rises above the diagonal
+///
+/// # Arguments
+/// * `wcss` - The WCSS of each run in order of `k`, must not be empty
+fn difference_curve(wcss: &[f32]) -> Vec<f32> {
+    let (min, max) = wcss
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &w| {
+            (lo.min(w), hi.max(w))
+        });
+    let span = if max > min { max - min } else { 1.0 };
+    let last = (wcss.len() - 1).max(1) as f32;
+
+    wcss.iter()
+        .enumerate()
+        .map(|(i, w)| (max - w) / span - i as f32 / last)
+        .collect()
+}
+
+/// The index of the knee of a kneedle difference curve, the first local
+/// maximum that the curve then falls below a threshold after before rising
+/// to another. The threshold is `sensitivity` steps of `k`, in normalized
+/// units, below the maximum. Returns `None` when no maximum qualifies
+///
+/// # Arguments
+/// * `differences` - The difference curve from `difference_curve`
+/// * `sensitivity` - How far the curve must fall, higher finds fewer knees
+fn find_knee(differences: &[f32], sensitivity: f32) -> Option<usize> {
+    let n = differences.len();
+    if n < 3 {
+        return None;
+    }
+    let step = 1.0 / (n - 1) as f32;
+    let is_maximum = |i: usize| {
+        differences[i] > differences[i - 1] && (i + 1 == n || differences[i] >= differences[i + 1])
+    };
+
+    (1..n - 1).filter(|&i| is_maximum(i)).find(|&i| {
+        let threshold = differences[i] - sensitivity * step;
+        (i + 1..n)
+            .take_while(|&j| !is_maximum(j))
+            .any(|j| differences[j] < threshold)
+    })
+}
+
+/// Returns a function finding the cluster nearest a color, and the distance
+/// to it, as a run in the given space and with the given metric would assign
+/// it. Ties go to the earliest cluster
+///
+/// # Arguments
+/// * `clusters` - The clusters to pick from, must not be empty
+/// * `space` - The color space to measure distances in
+/// * `metric` - How distances are measured in `space`
+fn nearest_cluster_in(
+    clusters: &[Color],
+    space: ColorSpace,
+    metric: DistanceMetric,
+) -> impl Fn(&Color) -> (usize, f32) {
+    let points: Vec<Point> = clusters.iter().map(|k| space.to_point(k)).collect();
+    move |color| metric.nearest(space, &space.to_point(color), &points)
+}
+
+/// Each cluster's share of the variance explained, as
+/// `RunResult.variance_explained` gives it. A point's share goes to its
+/// nearest cluster, by how much nearer that is than the mean of all the points
+///
+/// # Arguments
+/// * `points` - The points with their weights
+/// * `clusters` - The clusters in the same space as `points`
+/// * `space` - The color space the points are in
+/// * `metric` - How distances between the points are measured
+fn variance_shares(
+    points: &[(Point, f64)],
+    clusters: &[Point],
+    space: ColorSpace,
+    metric: DistanceMetric,
+) -> Vec<f32> {
+    let mut reductions = vec![0.0_f64; clusters.len()];
+    if clusters.is_empty() || points.is_empty() {
+        return vec![0.0; clusters.len()];
+    }
+    let mean = space.weighted_mean(points.iter().map(|(p, w)| (p, *w)));
+
+    for (point, weight) in points {
+        let (nearest, _) = metric.nearest(space, point, clusters);
+        // the mean isn't a whole color so RGB can't use the integer distance
+        let baseline = (metric.distance(space, point, &mean) as f64).powi(2);
+        reductions[nearest] +=
+            (baseline - metric.squared_distance(space, point, &clusters[nearest])) * weight;
+    }
+
+    let total: f64 = reductions.iter().sum();
+    if total <= 0.0 {
+        return vec![0.0; clusters.len()];
+    }
+    reductions.iter().map(|r| (r / total) as f32).collect()
+}
+
+/// Fold every color past the first `keep` of a histogram into the nearest of
+/// those first colors, adding its count and weight to that color's. The
+/// histogram is left sorted by frequency
+///
+/// # Arguments
+/// * `histogram` - The histogram, most frequent first
+/// * `keep` - How many colors to keep, at least 1
+/// * `space` - The color space to find the nearest kept color in
+fn fold_tail(histogram: &mut Vec<HistogramEntry>, keep: usize, space: ColorSpace) {
+    if histogram.len() <= keep {
+        return;
+    }
+    let tail = histogram.split_off(keep);
+    let kept: Vec<Point> = histogram
+        .iter()
+        .map(|(c, _, _)| space.to_point(c))
+        .collect();
+
+    for (color, count, weight) in tail {
+        let (nearest, _) = space.nearest(&space.to_point(&color), &kept);
+        histogram[nearest].1 += count;
+        histogram[nearest].2 += weight;
+    }
+    histogram.sort_by(by_frequency);
+}
+
+/// The number of distinct colors in a list of clusters
+fn count_distinct(clusters: &[Color]) -> usize {
+    clusters.iter().collect::<HashSet<_>>().len()
+}
+
+/// Read RGBA pixel data into a `Color` per pixel, checking it matches the
+/// given dimensions
+///
+/// # Arguments
+/// * `data` - The pixel data, 4 bytes per pixel in RGBA order
+/// * `width` - The width of the image
+/// * `height` - the height of the image
+fn parse_rgba(data: &[u8], width: u32, height: u32) -> Result<Vec<Color>, KmeansError> {
+    if data.is_empty() {
+        return Err(KmeansError::EmptyImage);
+    }
+    if data.len() != width as usize * height as usize * 4 {
+        return Err(KmeansError::InvalidDimensions);
+    }
+
+    Ok(data
+        .chunks_exact(4)
+        .map(|px| Color {
+            r: px[0] as i32,
+            g: px[1] as i32,
+            b: px[2] as i32,
+        })
+        .collect())
+}
+
+/// Sample every n-th pixel along both axes of RGBA pixel data, with n the
+/// smallest stride leaving at most `max` pixels. Returns the sampled pixel data
+/// along with its width and height
+///
+/// # Arguments
+/// * `data` - The pixel data, 4 bytes per pixel in RGBA order
+/// * `width` - The width of the image
+/// * `height` - the height of the image
+/// * `max` - The most pixels to keep
+fn sample_rgba(data: &[u8], width: u32, height: u32, max: usize) -> (Vec<u8>, u32, u32) {
+    let pixels = width as usize * height as usize;
+    let mut stride = ((pixels as f64 / max as f64).sqrt().ceil() as u32).max(1);
+    while (width.div_ceil(stride) as usize) * (height.div_ceil(stride) as usize) > max {
+        stride += 1;
+    }
+
+    let sampled = (0..height)
+        .step_by(stride as usize)
+        .flat_map(|y| {
+            (0..width).step_by(stride as usize).flat_map(move |x| {
+                let i = (y as usize * width as usize + x as usize) * 4;
+                data[i..i + 4].iter().copied()
+            })
+        })
+        .collect();
+
+    (sampled, width.div_ceil(stride), height.div_ceil(stride))
+}
+
+/// Shrink RGBA pixel data with a box filter, each new pixel averaging the
+/// block of pixels it covers. Colors are averaged weighted by their alpha so
+/// transparent pixels don't darken their neighbours, alpha is a plain average
+///
+/// # Arguments
+/// * `data` - The pixel data, 4 bytes per pixel in RGBA order
+/// * `width` - The width of the image
+/// * `height` - The height of the image
+/// * `target_width` - The new width, from 1 to `width`
+/// * `target_height` - The new height, from 1 to `height`
+fn box_downsample(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (target_width, target_height) = (target_width as usize, target_height as usize);
+    let mut downsampled = Vec::with_capacity(target_width * target_height * 4);
+
+    for ty in 0..target_height {
+        let (y0, y1) = (
+            ty * height / target_height,
+            (ty + 1) * height / target_height,
+        );
+        for tx in 0..target_width {
+            let (x0, x1) = (tx * width / target_width, (tx + 1) * width / target_width);
+            let (mut sums, mut alpha, mut count) = ([0_u64; 3], 0_u64, 0_u64);
+
+            for y in y0..y1 {
+                for px in data[(y * width + x0) * 4..(y * width + x1) * 4].chunks_exact(4) {
+                    for (sum, &channel) in sums.iter_mut().zip(px) {
+                        *sum += channel as u64 * px[3] as u64;
+                    }
+                    alpha += px[3] as u64;
+                    count += 1;
+                }
+            }
+
+            let channels = sums.map(|sum| match alpha {
+                0 => 0,
+                _ => ((sum + alpha / 2) / alpha) as u8,
+            });
+            downsampled.extend(channels);
+            downsampled.push(((alpha + count / 2) / count) as u8);
+        }
+    }
+
+    downsampled
+}
+
+/// Merge each color into the first, so most frequent, earlier color within
+/// `delta_e` of it in CIE Lab, summing the counts and weights of merged colors
+/// into their representative
+///
+/// # Arguments
+/// * `sorted` - Colors with their counts and weights, most frequent first
+/// * `delta_e` - The CIE76 delta-E within which colors are merged
+fn merge_similar_colors(sorted: Vec<HistogramEntry>, delta_e: f32) -> Vec<HistogramEntry> {
+    // Euclidean distance in LCh, with its chord hue term, is the Lab delta-E
+    let space = ColorSpace::Lch;
+    let mut merged: Vec<HistogramEntry> = vec![];
+    let mut points: Vec<Point> = vec![];
+
+    for (color, count, weight) in sorted {
+        let point = space.to_point(&color);
+        match points
+            .iter()
+            .position(|p| space.distance(p, &point) <= delta_e)
+        {
+            Some(i) => {
+                merged[i].1 += count;
+                merged[i].2 += weight;
+            }
+            None => {
+                merged.push((color, count, weight));
+                points.push(point);
+            }
+        }
+    }
+
+    merged
+}
+
+/// Represents the instance of the module containing the current images
+/// pixel Colors and the last set of RunResults
+#[wasm_bindgen]
+#[derive(Serialize)]
+pub struct ImageKmeans {
+    colors: Vec<Color>,
+    alpha: Vec<u8>,
+    sample_rate: f32,
+    width: u32,
+    height: u32,
+    added_counts: BTreeMap<Color, usize>,
+    /// A weight for each pixel of `colors`, from `from_rgba_weighted`
+    pixel_weights: Option<Vec<f32>>,
+    quantize_fact: i32,
+    working_colors: Vec<Color>,
+    working_colors_counts: BTreeMap<Color, usize>,
+    /// The total pixel weight of each working color, the same as its count
+    /// unless the instance was created with `from_rgba_weighted`
+    working_colors_weights: BTreeMap<Color, f64>,
+    working_points: Vec<Point>,
+    /// `working_points` with `Config.jitter` added, empty without it
+    jittered_points: Vec<Point>,
+    initial_ks: Vec<Color>,
+    results: Vec<RunResult>,
+    selected_result: usize,
+    selection_detail: Option<SelectionDetail>,
+    /// The `Config` `results` were clustered with, so pixels are matched to
+    /// their clusters as the runs matched them
+    #[serde(skip)]
+    results_config: Config,
+    #[serde(skip)]
+    random: Box<dyn RandomSource>,
+    /// Recent seeded fixed `k` runs, most recently used last
+    #[serde(skip)]
+    run_cache: VecDeque<(usize, Config, RunResult)>,
+    /// The full sorted histogram of the latest run before any `top_num` or
+    /// `top_percent` limit, along with the options it was built for
+    #[serde(skip)]
+    histogram: Option<(HistogramKey, Vec<HistogramEntry>)>,
+    /// How long the latest working colors and initial clusters took, the
+    /// iterations are timed within each run
+    #[serde(skip)]
+    timing: RunTiming,
+}
+
+#[wasm_bindgen]
+impl ImageKmeans {
+    /// Creates an instance of the ImageKmeans. This is also decorated as
+    /// the JS constructor so will be run directly as a call to
+    ///
+    /// ```js
+    /// const kmeans = new ImageKmeans(ctx, height, width);
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The canvas 2d rendering context containing the image
+    /// * `width` - The width of the rendered image
+    /// * `height` - the height of the rendered image
+    /// * `max_colors` - Optional cap on the number of pixels kept, see
+    ///   `from_rgba_capped`
+    #[cfg(feature = "web")]
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        ctx: &CanvasRenderingContext2d,
+        width: u32,
+        height: u32,
+        max_colors: Option<usize>,
+    ) -> Result<ImageKmeans, KmeansError> {
+        if width == 0 || height == 0 {
+            return Err(KmeansError::InvalidDimensions);
+        }
+
+        let image_data = ctx
+            .get_image_data(0.0, 0.0, width as f64, height as f64)
+            .unwrap();
+
+        ImageKmeans::from_rgba_capped(&image_data.data(), width, height, max_colors)
+    }
+
+    /// Creates an instance of the ImageKmeans directly from raw RGBA pixel
+    /// data, such as the `data` of an `ImageData`, without needing a canvas
+    ///
+    /// ```js
+    /// const kmeans = ImageKmeans.from_rgba(imageData.data, width, height);
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The pixel data, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of the image
+    /// * `height` - the height of the image
+    pub fn from_rgba(data: &[u8], width: u32, height: u32) -> Result<ImageKmeans, KmeansError> {
+        ImageKmeans::from_rgba_capped(data, width, height, None)
+    }
+
+    /// Creates an instance of the ImageKmeans from raw RGBA pixel data as
+    /// `from_rgba` does, keeping at most `max_colors` pixels to bound memory
+    /// use on huge images. Beyond the cap the image is sampled every n-th
+    /// pixel along both axes, keeping a smaller grid so the pixels remain an
+    /// image of their own. The fraction of pixels kept is available from
+    /// `sample_rate`
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The pixel data, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of the image
+    /// * `height` - the height of the image
+    /// * `max_colors` - The most pixels to keep, `None` keeps every pixel
+    pub fn from_rgba_capped(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        max_colors: Option<usize>,
+    ) -> Result<ImageKmeans, KmeansError> {
+        set_panic_hook();
+        if max_colors == Some(0) {
+            return Err(KmeansError::InvalidConfig {
+                reason: "max_colors must be at least 1".to_string(),
+            });
+        }
+
+        let pixels = width as usize * height as usize;
+        let sampled;
+        let (data, sampled_width, sampled_height) = match max_colors {
+            Some(max) if pixels > max && data.len() == pixels * 4 => {
+                sampled = sample_rgba(data, width, height, max);
+                (&sampled.0[..], sampled.1, sampled.2)
+            }
+            _ => (data, width, height),
+        };
+        let colors = parse_rgba(data, sampled_width, sampled_height)?;
+
+        let mut kmeans = ImageKmeans {
+            sample_rate: colors.len() as f32 / pixels as f32,
+            colors,
+            alpha: data.chunks_exact(4).map(|px| px[3]).collect(),
+            width: sampled_width,
+            height: sampled_height,
+            ..ImageKmeans::blank()
+        };
+        kmeans.set_working_colors(&Config::default())?;
+
+        Ok(kmeans)
+    }
+
+    /// Creates an instance of the ImageKmeans from raw RGBA pixel data as
+    /// `from_rgba` does, first shrinking the image to at most
+    /// `target_width` x `target_height` with a box filter. Each kept pixel is
+    /// the average of the block of pixels it covers rather than a single
+    /// picked pixel as `from_rgba_capped` keeps, so thin features still count
+    /// towards the colors. The fraction of pixels kept is available from
+    /// `sample_rate`
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The pixel data, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of the image
+    /// * `height` - the height of the image
+    /// * `target_width` - The most pixels to keep across, limited to `width`
+    /// * `target_height` - The most pixels to keep down, limited to `height`
+    pub fn from_rgba_downsampled(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        target_width: u32,
+        target_height: u32,
+    ) -> Result<ImageKmeans, KmeansError> {
+        if target_width == 0 || target_height == 0 {
+            return Err(KmeansError::InvalidDimensions);
+        }
+        let pixels = width as usize * height as usize;
+        if data.is_empty() || data.len() != pixels * 4 {
+            return ImageKmeans::from_rgba(data, width, height);
+        }
+
+        let (target_width, target_height) = (target_width.min(width), target_height.min(height));
+        let downsampled = box_downsample(data, width, height, target_width, target_height);
+        let mut kmeans = ImageKmeans::from_rgba(&downsampled, target_width, target_height)?;
+        kmeans.sample_rate = kmeans.colors.len() as f32 / pixels as f32;
+
+        Ok(kmeans)
+    }
+
+    /// Creates an instance of the ImageKmeans from raw RGBA pixel data as
+    /// `from_rgba` does, with an importance weight for each pixel such as one
+    /// from a saliency model. Cluster means and the WCSS weight each pixel by
+    /// its weight in place of counting it once, so the palette leans towards
+    /// the heavily weighted pixels. Pixels weighted `0.0` are left out
+    ///
+    /// ```js
+    /// const kmeans = ImageKmeans.from_rgba_weighted(imageData.data, width, height, saliency);
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The pixel data, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of the image
+    /// * `height` - the height of the image
+    /// * `weights` - One finite, non-negative weight per pixel in the same order
+    pub fn from_rgba_weighted(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        weights: &[f32],
+    ) -> Result<ImageKmeans, KmeansError> {
+        if weights.len() != width as usize * height as usize {
+            return Err(KmeansError::InvalidConfig {
+                reason: "weights must have one entry per pixel".to_string(),
+            });
+        }
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(KmeansError::InvalidConfig {
+                reason: "weights must be finite and not negative".to_string(),
+            });
+        }
+
+        let mut kmeans = ImageKmeans::from_rgba(data, width, height)?;
+        kmeans.pixel_weights = Some(weights.to_vec());
+        kmeans.histogram = None;
+        kmeans.set_working_colors(&Config::default())?;
+
+        Ok(kmeans)
+    }
+
+    /// Add the colors of another image drawn on a canvas to this instance so
+    /// the next run clusters the combined color distribution of every image,
+    /// each weighted by its pixel count
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The canvas 2d rendering context containing the image
+    /// * `width` - The width of the rendered image
+    /// * `height` - the height of the rendered image
+    #[cfg(feature = "web")]
+    pub fn add_image(
+        &mut self,
+        ctx: &CanvasRenderingContext2d,
+        width: u32,
+        height: u32,
+    ) -> Result<(), KmeansError> {
+        if width == 0 || height == 0 {
+            return Err(KmeansError::InvalidDimensions);
+        }
+
+        let image_data = ctx
+            .get_image_data(0.0, 0.0, width as f64, height as f64)
+            .unwrap();
+
+        self.add_rgba(&image_data.data(), width, height)
+    }
+
+    /// Add the colors of another image from raw RGBA pixel data so the next
+    /// run clusters the combined color distribution of every image, each
+    /// weighted by its pixel count. Added images contribute plain pixel
+    /// counts, per-pixel options such as `coherence_weight` and `error_map`
+    /// only cover the image the instance was created from
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The pixel data, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of the image
+    /// * `height` - the height of the image
+    pub fn add_rgba(&mut self, data: &[u8], width: u32, height: u32) -> Result<(), KmeansError> {
+        for color in parse_rgba(data, width, height)? {
+            *self.added_counts.entry(color).or_insert(0) += 1;
+        }
+        self.run_cache.clear();
+        self.histogram = None;
+        self.set_working_colors(&Config::default())?;
+
+        Ok(())
+    }
+
+    /// Adapt the clusters of the latest `RunResult` to a new frame drawn on a
+    /// canvas, for palettes following live video at a low cost per frame. See
+    /// `update_online_rgba`
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The canvas 2d rendering context containing the frame
+    /// * `width` - The width of the rendered frame
+    /// * `height` - the height of the rendered frame
+    /// * `learning_rate` - How far each cluster moves towards its new mean
+    #[cfg(feature = "web")]
+    pub fn update_online(
+        &mut self,
+        ctx: &CanvasRenderingContext2d,
+        width: u32,
+        height: u32,
+        learning_rate: f32,
+    ) -> Result<RunResult, KmeansError> {
+        if width == 0 || height == 0 {
+            return Err(KmeansError::InvalidDimensions);
+        }
+
+        let image_data = ctx
+            .get_image_data(0.0, 0.0, width as f64, height as f64)
+            .unwrap();
+
+        self.update_online_rgba(&image_data.data(), width, height, learning_rate)
+    }
+
+    /// Adapt the clusters of the latest `RunResult` to a new frame with a
+    /// single online k-means step. Pixels count as the run's `alpha_mode`
+    /// counts them, each is assigned to its nearest cluster in the run's
+    /// `color_space` and `distance_metric`, and
+    /// each cluster moves `learning_rate` of the way towards the RGB mean of
+    /// its pixels, clusters with none stay put. The result is updated in place
+    /// with the frame's `counts`, `variance_explained` and WCSS, measured as
+    /// the run measured them, and returned, or `KmeansError::NoRun` if there
+    /// hasn't been a run yet. The step counts as the result's only iteration,
+    /// its `trace`, `centroid_history`, `float_centroids` and `timing` are
+    /// cleared. Lowering `learning_rate` over time settles the palette
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The frame's pixel data, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of the frame
+    /// * `height` - the height of the frame
+    /// * `learning_rate` - How far each cluster moves, above 0 and at most 1
+    pub fn update_online_rgba(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        learning_rate: f32,
+    ) -> Result<RunResult, KmeansError> {
+        if !(learning_rate > 0.0 && learning_rate <= 1.0) {
+            return Err(KmeansError::InvalidConfig {
+                reason: format!(
+                    "learning_rate ({}) must be above 0 and at most 1",
+                    learning_rate
+                ),
+            });
+        }
+        let colors = parse_rgba(data, width, height)?;
+        let mut clusters = match self.latest_result() {
+            Some(result) if !result.clusters.is_empty() => result.clusters.clone(),
+            _ => return Err(KmeansError::NoRun),
+        };
+
+        let (space, metric) = self.results_measure();
+
+        let alpha_mode = self.results_config.alpha_mode;
+        let mut histogram: BTreeMap<Color, usize> = BTreeMap::new();
+        for (color, px) in colors.iter().zip(data.chunks_exact(4)) {
+            let count = alpha_mode.pixel_count(px[3]);
+            if count > 0 {
+                *histogram
+                    .entry(alpha_mode.pixel_color(color, px[3]))
+                    .or_insert(0) += count;
+            }
+        }
+        let assign = nearest_cluster_in(&clusters, space, metric);
+        let mut sums = vec![([0_u64; 3], 0_u64); clusters.len()];
+        for (color, &count) in &histogram {
+            let (nearest, _) = assign(color);
+            let (channels, total) = &mut sums[nearest];
+            for (sum, v) in channels.iter_mut().zip([color.r, color.g, color.b]) {
+                *sum += v as u64 * count as u64;
+            }
+            *total += count as u64;
+        }
+
+        for (cluster, (channels, total)) in clusters.iter_mut().zip(&sums) {
+            if *total == 0 {
+                continue;
+            }
+            let step = |c: i32, sum: u64| {
+                let mean = sum as f32 / *total as f32;
+                (c as f32 + (mean - c as f32) * learning_rate).round() as i32
+            };
+            *cluster = Color {
+                r: step(cluster.r, channels[0]),
+                g: step(cluster.g, channels[1]),
+                b: step(cluster.b, channels[2]),
+            };
+        }
+
+        let points: Vec<(Point, f64)> = histogram
+            .iter()
+            .map(|(color, &count)| (space.to_point(color), count as f64))
+            .collect();
+        let cluster_points: Vec<Point> = clusters.iter().map(|k| space.to_point(k)).collect();
+        let mut counts = vec![0; clusters.len()];
+        let mut wcss = 0.0;
+        for ((point, weight), &count) in points.iter().zip(histogram.values()) {
+            let (nearest, _) = metric.nearest(space, point, &cluster_points);
+            counts[nearest] += count;
+            wcss += metric.squared_distance(space, point, &cluster_points[nearest]) * weight;
+        }
+
+        let result = &mut self.results[self.selected_result];
+        result.distinct = count_distinct(&clusters);
+        result.clusters = clusters;
+        result.counts = counts;
+        result.variance_explained = variance_shares(&points, &cluster_points, space, metric);
+        result.wcss = wcss as f32;
+        result.iterations = 1;
+        result.convergence = vec![result.wcss];
+        result.trace = None;
+        result.centroid_history = None;
+        result.float_centroids = None;
+        result.timing = None;
+        Ok(result.clone())
+    }
+
+    /// Returns the fraction of the image's pixels that were kept, below `1.0`
+    /// when a `max_colors` cap caused the image to be sampled
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Returns the Shannon entropy, in bits, of the working color histogram
+    /// from the latest run, or of every color in the image if there hasn't
+    /// been one. Images of a few flat colors score low and rich photographic
+    /// images score high, making this a cheap guide for picking `k`
+    pub fn color_entropy(&self) -> f32 {
+        let total: usize = self.working_colors_counts.values().sum();
+        if total == 0 {
+            return 0.0;
+        }
+
+        self.working_colors_counts
+            .values()
+            .map(|&count| {
+                let p = count as f64 / total as f64;
+                p * (1.0 / p).log2()
+            })
+            .sum::<f64>() as f32
+    }
+
+    /// Returns a JSON summary of the instance's state for bug reports, giving
+    /// the image size, distinct and working color counts, the initial clusters
+    /// and the latest results. The per-pixel data is left out so this stays
+    /// small for any size of image
+    pub fn debug_state(&self) -> String {
+        serde_json::to_string(&StateSummary {
+            width: self.width,
+            height: self.height,
+            sample_rate: self.sample_rate,
+            distinct_colors: self.raw_histogram().len(),
+            working_colors: self.working_colors.len(),
+            quantize_fact: self.quantize_fact,
+            initial_ks: &self.initial_ks,
+            results: &self.results,
+            selected_result: self.selected_result,
+        })
+        .unwrap()
+    }
+
+    /// Returns the quantization factor used for the latest run, this is how a
+    /// `target_distinct` budget was met
+    pub fn quantize_fact(&self) -> i32 {
+        self.quantize_fact
+    }
+
+    /// Returns the ResultSet from the current run to JS as an array of `RunResult`s,
+    /// in the case where no run has happend yet an empty array will be returned
+    ///
+    /// @deprecated
+    pub fn get_result_set(&self) -> JsValue {
+        JsValue::from_serde(&self.results).unwrap()
+    }
+
+    /// Do a run with a fixed number of `k` clusters and return the result set to JS
+    /// as a single `RunResult`. When `config.seed` is set the result is also
+    /// remembered, so repeating the same `k` and config returns it without
+    /// running again
+    ///
+    /// # Arguments
+    ///
+    /// * `k_number` - The number of `k` clusters to use for this run
+    /// * `config` - Optional `Config` for the run
+    pub fn with_fixed_k_number(
+        &mut self,
+        k_number: usize,
+        config: Option<Config>,
+    ) -> Result<RunResult, KmeansError> {
+        let config = config.unwrap_or_default();
+        config.validate()?;
+        self.check_can_run()?;
+        config.validate_k(k_number)?;
+        self.set_working_colors(&config)?;
+
+        let result = match self.cached_run(k_number, &config) {
+            Some(result) => result,
+            None => {
+                let mut result = self.fresh_run(k_number, &config, &mut || false)?;
+                config.add_warnings(k_number, &mut result);
+                if config.seed.is_some() {
+                    if self.run_cache.len() == RUN_CACHE_SIZE {
+                        self.run_cache.pop_front();
+                    }
+                    self.run_cache
+                        .push_back((k_number, config.clone(), result.clone()));
+                }
+                result
+            }
+        };
+
+        self.results = vec![result];
+        self.selected_result = 0;
+        self.selection_detail = None;
+        self.results_config = config.clone();
+
+        // JsValue::from_serde(&self.results[0]).unwrap()
+        Ok(self.results[0].clone())
+    }
+
+    /// Do a run for each of several fixed `k` numbers and return all of the
+    /// results in the same order. The initial clusters are picked once for the
+    /// largest `k` and each run takes its prefix of them, so the results are
+    /// directly comparable
+    ///
+    /// # Arguments
+    ///
+    /// * `ks` - The `k` numbers to do runs for
+    /// * `config` - Optional `Config` applied to every run
+    pub fn fixed_k_batch(
+        &mut self,
+        ks: Vec<usize>,
+        config: Option<Config>,
+    ) -> Result<RunResults, KmeansError> {
+        let config = config.unwrap_or_default();
+        config.validate()?;
+        self.check_can_run()?;
+        for &k in &ks {
+            config.validate_k(k)?;
+        }
+        self.set_working_colors(&config)?;
+
+        if config.grayscale_split.is_some() {
+            self.results = ks
+                .iter()
+                .map(|&k| self.fresh_run(k, &config, &mut || false))
+                .collect::<Result<_, _>>()?;
+        } else {
+            self.use_initial_ks(ks.iter().copied().max().unwrap_or(0), &config);
+            self.results = ks
+                .iter()
+                .map(|&k| self.do_run(k, &config, &mut || false))
+                .collect::<Result<_, _>>()?;
+        }
+        for (&k, result) in ks.iter().zip(&mut self.results) {
+            config.add_warnings(k, result);
+        }
+        self.selected_result = self.results.len().saturating_sub(1);
+        self.selection_detail = None;
+        self.results_config = config.clone();
+
+        Ok(RunResults(self.results.clone()))
+    }
+
+    /// Do the same fixed `k` run at each of several quantization factors and
+    /// return all of the results in the same order, to find the coarsest
+    /// quantization that still gives a stable palette. Each run uses `config`
+    /// with its `quantize_fact` replaced by the factor, so `config` can't set
+    /// `target_distinct`. Give `config` a `seed` for the runs to be comparable
+    ///
+    /// # Arguments
+    ///
+    /// * `factors` - The quantization factors to do runs at
+    /// * `k_number` - The number of `k` clusters for every run
+    /// * `config` - Optional `Config` applied to every run
+    pub fn quantization_sweep(
+        &mut self,
+        factors: Vec<i32>,
+        k_number: usize,
+        config: Option<Config>,
+    ) -> Result<RunResults, KmeansError> {
+        let config = config.unwrap_or_default();
+        let configs: Vec<Config> = factors
+            .iter()
+            .map(|&fact| Config {
+                quantize_fact: Some(fact),
+                ..config.clone()
+            })
+            .collect();
+        for config in &configs {
+            config.validate()?;
+        }
+        self.check_can_run()?;
+        config.validate_k(k_number)?;
+
+        let mut results = Vec::with_capacity(configs.len());
+        for config in &configs {
+            self.set_working_colors(config)?;
+            let mut result = self.fresh_run(k_number, config, &mut || false)?;
+            config.add_warnings(k_number, &mut result);
+            results.push(result);
+        }
+        self.results = results;
+        self.selected_result = self.results.len().saturating_sub(1);
+        self.selection_detail = None;
+        self.results_config = config.clone();
+
+        Ok(RunResults(self.results.clone()))
+    }
+
+    /// Performs multiple runs using `k` numbers between 1 and 20 and then uses
+    /// analysis to determine the most appropriate number of `k` clusters to use
+    /// for the provided image. Once determined the `RunResult` for this `k` number
+    /// is returned
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Optional `Config` applied to every run
+    pub fn with_derived_k_number(
+        &mut self,
+        config: Option<Config>,
+    ) -> Result<RunResult, KmeansError> {
+        self.with_derived_k_number_cancellable(config, || false)
+    }
+
+    /// Performs the same runs as `with_derived_k_number` but calls
+    /// `should_cancel` between each run and each iteration, stopping with a
+    /// `KmeansError::Cancelled` as soon as it returns something truthy. The
+    /// runs completed before cancelling are left in the result set
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Optional `Config` applied to every run
+    /// * `should_cancel` - Called with no arguments to check for cancellation
+    pub fn with_derived_k_number_interruptible(
+        &mut self,
+        config: Option<Config>,
+        should_cancel: &js_sys::Function,
+    ) -> Result<RunResult, KmeansError> {
+        self.with_derived_k_number_cancellable(config, || {
+            should_cancel
+                .call0(&JsValue::NULL)
+                .is_ok_and(|v| v.is_truthy())
+        })
+    }
+
+    /// Returns how the latest `with_derived_k_number` chose its `k`, this is
+    /// `undefined` if there hasn't been one or another kind of run has been
+    /// made since
+    pub fn selection_detail(&self) -> Option<SelectionDetail> {
+        self.selection_detail.clone()
+    }
+
+    /// Returns the results of the latest `with_derived_k_number` with the `n`
+    /// best scores from its `SelectionDetail`, best first, so near ties can be
+    /// offered as alternatives to the chosen `k`. Equal scores are ordered by
+    /// `k`. The array is empty if there hasn't been a derived run or another
+    /// kind of run has been made since
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - The most results to return, the chosen one included
+    pub fn alternative_ks(&self, n: usize) -> RunResults {
+        let Some(detail) = &self.selection_detail else {
+            return RunResults(vec![]);
+        };
+        let mut order: Vec<usize> = (0..detail.distances.len()).collect();
+        order.sort_by(|&a, &b| detail.distances[b].total_cmp(&detail.distances[a]));
+
+        RunResults(
+            order
+                .into_iter()
+                .take(n)
+                .map(|i| self.results[i].clone())
+                .collect(),
+        )
+    }
+
+    /// Returns the `k` number and WCSS of each result from the latest run along
+    /// with the index of the result that was returned. After
+    /// `with_derived_k_number` these are the points the elbow analysis picked
+    /// from, if there hasn't been a run yet `points` is empty
+    pub fn elbow_curve(&self) -> ElbowCurve {
+        ElbowCurve {
+            points: self.results.iter().map(|r| (r.ks, r.wcss)).collect(),
+            selected: self.selected_result,
+        }
+    }
+
+    /// Returns the clusters of the latest `RunResult` as zero-padded uppercase
+    /// hex strings such as `#0A1B2C`. If there hasn't been a run yet the
+    /// returned array is empty
+    pub fn palette_hex(&self) -> Vec<String> {
+        self.latest_result()
+            .map(|result| result.clusters.iter().map(Color::to_hex).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns an entry for each cluster of the latest `RunResult` with its hex
+    /// string, pixel count and fraction of all pixels, everything needed for
+    /// swatches with proportion bars in one call. If there hasn't been a run
+    /// yet the returned array is empty
+    pub fn palette_json_with_counts(&self) -> PaletteEntries {
+        PaletteEntries(self.palette_iter().collect())
+    }
+
+    /// Returns the clusters of the latest `RunResult` as `[r, g, b]` arrays with
+    /// each channel divided by 255 into the range `0.0..=1.0`, ready for
+    /// uploading as a shader uniform. If there hasn't been a run yet the
+    /// returned array is empty
+    pub fn palette_normalized(&self) -> NormalizedPalette {
+        NormalizedPalette(
+            self.latest_result()
+                .map(|result| {
+                    result
+                        .clusters
+                        .iter()
+                        .map(|c| [c.r, c.g, c.b].map(|v| v as f32 / 255.0))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Map the image onto the 216 web-safe colors as `quantize_to_palette`
+    /// does, returning the web-safe colors used with their counts and the WCSS
+    pub fn quantize_to_web_safe(&self) -> RunResult {
+        self.palette_result(&Color::web_safe())
+    }
+
+    /// Returns the clusters of the latest `RunResult` along with a display color
+    /// for each, picked from Kelly's 22 colors of maximum contrast to be as far
+    /// apart as possible in Lab, with each cluster getting the nearest of them.
+    /// Keeps the analysis faithful while the legend stays readable, clusters
+    /// past the 22nd keep their own color. If there hasn't been a run yet both
+    /// arrays are empty
+    pub fn display_palette(&self) -> DisplayPalette {
+        let palette = self
+            .latest_result()
+            .map(|result| result.clusters.clone())
+            .unwrap_or_default();
+
+        DisplayPalette {
+            display: kelly::display_colors(&palette),
+            palette,
+        }
+    }
+
+    /// Returns a gradient of `steps` colors running through the clusters of the
+    /// latest `RunResult` from darkest to lightest by relative luminance. The
+    /// clusters are spread evenly along the ramp and blended in linear light
+    /// between, so the luminance never decreases along it. If there hasn't
+    /// been a run yet the returned array is empty
+    ///
+    /// # Arguments
+    ///
+    /// * `steps` - The number of colors in the gradient
+    pub fn palette_gradient(&self, steps: usize) -> ColorRamp {
+        let mut palette = match self.latest_result() {
+            Some(result) if !result.clusters.is_empty() => result.clusters.clone(),
+            _ => return ColorRamp(vec![]),
+        };
+        palette.sort_by(|a, b| a.relative_luminance().total_cmp(&b.relative_luminance()));
+
+        let segments = (palette.len() - 1) as f32;
+        ColorRamp(
+            (0..steps)
+                .map(|i| {
+                    let position = match steps {
+                        1 => 0.0,
+                        _ => i as f32 / (steps - 1) as f32 * segments,
+                    };
+                    let from = (position.floor() as usize).min(palette.len() - 1);
+                    let to = (from + 1).min(palette.len() - 1);
+                    Color::blend_linear(&palette[from], &palette[to], position - from as f32)
+                })
+                .collect(),
+        )
+    }
+
+    /// Recount the clusters of the latest `RunResult` against another version
+    /// of the image, with one assignment pass of its pixels to their nearest
+    /// cluster in the run's `color_space` and `distance_metric`. This lets the
+    /// clusters be found quickly on a thumbnail while the `counts` reflect the
+    /// full resolution image. The updated result is returned, or
+    /// `KmeansError::NoRun` if there hasn't been a run yet
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The full resolution pixel data, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of the full resolution image
+    /// * `height` - the height of the full resolution image
+    pub fn recount_rgba(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<RunResult, KmeansError> {
+        let colors = parse_rgba(data, width, height)?;
+        let clusters = match self.latest_result() {
+            Some(result) if !result.clusters.is_empty() => &result.clusters,
+            _ => return Err(KmeansError::NoRun),
+        };
+        let (space, metric) = self.results_measure();
+        let assign = nearest_cluster_in(clusters, space, metric);
+        let mut nearest: BTreeMap<Color, usize> = BTreeMap::new();
+        let mut counts = vec![0; clusters.len()];
+
+        for color in colors {
+            let index = *nearest.entry(color).or_insert_with_key(|c| assign(c).0);
+            counts[index] += 1;
+        }
+
+        let selected = self.selected_result;
+        self.results[selected].counts = counts;
+        Ok(self.results[selected].clone())
+    }
+
+    /// Classify the latest `RunResult` as monochrome, duotone or polychrome.
+    /// Clusters covering less than 5% of the pixels are ignored, as are greys,
+    /// and the hues of the rest are grouped where they're within 30° of each
+    /// other in LCh. Best used after `with_derived_k_number` so the clusters
+    /// follow the image's own structure. `undefined` if there hasn't been a
+    /// run yet
+    pub fn color_theme(&self) -> Option<ColorTheme> {
+        let result = self.latest_result()?;
+        let total: usize = result.counts.iter().sum();
+
+        let mut hues: Vec<f32> = result
+            .clusters
+            .iter()
+            .zip(&result.counts)
+            .filter(|(_, &count)| count as f32 >= THEME_MIN_FRACTION * total as f32)
+            .map(|(color, _)| ColorSpace::Lch.to_point(color))
+            .filter(|[_, chroma, _]| *chroma >= THEME_MIN_CHROMA)
+            .map(|[_, _, hue]| hue)
+            .collect();
+        hues.sort_by(f32::total_cmp);
+
+        // count the gaps around the hue circle wider than the spread, each
+        // separates two hue groups
+        let gaps = hues
+            .iter()
+            .zip(hues.iter().cycle().skip(1))
+            .filter(|(a, b)| (*b - *a).rem_euclid(360.0) > THEME_HUE_SPREAD)
+            .count();
+
+        Some(match gaps {
+            0 | 1 => ColorTheme::Monochrome,
+            2 => ColorTheme::Duotone,
+            _ => ColorTheme::Polychrome,
+        })
+    }
+
+    /// Summarize the latest `RunResult` as its warmth, saturation and
+    /// lightness, each weighted by the cluster counts. Warm colors are those
+    /// with an LCh hue from 330° around to 110° and at least the chroma
+    /// `color_theme` takes to not be grey. Every value is `0.0` if there
+    /// hasn't been a run yet
+    pub fn palette_summary(&self) -> PaletteSummary {
+        let Some(result) = self.latest_result() else {
+            return PaletteSummary::default();
+        };
+        let total: usize = result.counts.iter().sum();
+        if total == 0 {
+            return PaletteSummary::default();
+        }
+
+        let mut summary = PaletteSummary::default();
+        for (color, &count) in result.clusters.iter().zip(&result.counts) {
+            let share = count as f32 / total as f32;
+            let [lightness, chroma, hue] = ColorSpace::Lch.to_point(color);
+            let [value, hsv_chroma, _] = ColorSpace::Hsv.to_point(color);
+
+            if chroma >= THEME_MIN_CHROMA && !(WARM_HUE_END..WARM_HUE_START).contains(&hue) {
+                summary.warmth += share;
+            }
+            if value > 0.0 {
+                summary.saturation += share * hsv_chroma / value;
+            }
+            summary.lightness += share * (lightness / 100.0).clamp(0.0, 1.0);
+        }
+
+        summary
+    }
+
+    /// Returns the pixel counts of the working colors binned into a cube of
+    /// `bins_per_channel` bins along each channel, as a flat array of
+    /// `bins_per_channel³` counts indexed by `(r * bins + g) * bins + b`
+    /// with each channel's bin. Each bin covers an equal share of the
+    /// channel's range. The working colors are from the latest run, or the
+    /// default config before any run. Returns a `KmeansError::InvalidConfig`
+    /// if `bins_per_channel` is `0` or above `256`
+    ///
+    /// # Arguments
+    /// * `bins_per_channel` - The number of bins along each channel, `256`
+    ///   gives a bin to every channel value
+    pub fn color_cube(&self, bins_per_channel: usize) -> Result<Vec<u32>, KmeansError> {
+        let bins = bins_per_channel;
+        let channel_values = CHANNEL_MAX as usize + 1;
+        if !(1..=channel_values).contains(&bins) {
+            return Err(KmeansError::InvalidConfig {
+                reason: format!(
+                    "bins_per_channel ({}) must be from 1 to {}",
+                    bins, channel_values
+                ),
+            });
+        }
+
+        let mut cube = vec![0_u32; bins.pow(3)];
+        let bin = |v: i32| v.clamp(0, CHANNEL_MAX) as usize * bins / channel_values;
+        for (color, &count) in &self.working_colors_counts {
+            let index = (bin(color.r) * bins + bin(color.g)) * bins + bin(color.b);
+            cube[index] = cube[index].saturating_add(count.try_into().unwrap_or(u32::MAX));
+        }
+
+        Ok(cube)
+    }
+
+    /// Returns the WCAG contrast ratio between every pair of clusters in the
+    /// latest `RunResult`, as `[[i, j], ratio]` with `i < j` indexing the
+    /// clusters. A ratio of at least 4.5 is enough for normal text over a
+    /// background. If there hasn't been a run yet the returned array is empty
+    pub fn contrast_ratios(&self) -> ContrastPairs {
+        let clusters = self
+            .latest_result()
+            .map(|r| r.clusters.as_slice())
+            .unwrap_or_default();
+        let mut pairs = vec![];
+
+        for (i, a) in clusters.iter().enumerate() {
+            for (j, b) in clusters.iter().enumerate().skip(i + 1) {
+                pairs.push(((i, j), a.contrast_ratio(b)));
+            }
+        }
+
+        ContrastPairs(pairs)
+    }
+
+    /// Returns, for every pixel of the image in scan order, the distance from
+    /// the pixel to its nearest cluster in the latest `RunResult`, both
+    /// measured in the run's `color_space` with its `distance_metric`. This
+    /// can be rendered as a heatmap of how lossy the palette is, if there
+    /// hasn't been a run yet the returned array is empty
+    pub fn error_map(&self) -> Vec<f32> {
+        match self.latest_result() {
+            Some(result) if !result.clusters.is_empty() => {
+                let (space, metric) = self.results_measure();
+                let assign = nearest_cluster_in(&result.clusters, space, metric);
+                self.colors.iter().map(|color| assign(color).1).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Returns the image as RGBA pixel data with every pixel replaced by its
+    /// nearest cluster in the latest `RunResult`. Each pixel keeps its original
+    /// alpha byte so transparent regions survive the recolor, if there hasn't
+    /// been a run yet the image is returned unchanged
+    pub fn recolored_rgba(&self) -> Vec<u8> {
+        self.render_quantized(self.width, self.height)
+    }
+
+    /// Recolor other images, such as the frames of a video or a sprite sheet,
+    /// with the clusters of the latest `RunResult` so they all share one
+    /// palette without clustering each. Every pixel is replaced by its nearest
+    /// cluster, in the run's `color_space` and `distance_metric`, keeping its
+    /// alpha. Each color is only matched once across all of the frames.
+    /// Returns `KmeansError::NoRun` if there hasn't been a run
+    ///
+    /// # Arguments
+    ///
+    /// * `frames` - The pixel data of each image, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of every image
+    /// * `height` - the height of every image
+    pub fn recolor_images(
+        &self,
+        frames: Vec<js_sys::Uint8Array>,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<js_sys::Uint8Array>, KmeansError> {
+        let frames: Vec<Vec<u8>> = frames.iter().map(|frame| frame.to_vec()).collect();
+        let frames: Vec<&[u8]> = frames.iter().map(Vec::as_slice).collect();
+
+        Ok(self
+            .recolor_frames(&frames, width, height)?
+            .iter()
+            .map(|frame| js_sys::Uint8Array::from(frame.as_slice()))
+            .collect())
+    }
+
+    /// Returns `width` x `height` RGBA pixel data of the image with every pixel
+    /// replaced by its nearest cluster in the latest `RunResult`, measured in
+    /// the run's `color_space` and `distance_metric`, keeping its original
+    /// alpha. The image is scaled to the given size by nearest
+    /// neighbour, so a sampled image can be rendered back at its full size.
+    /// Needs no DOM so works from workers and native code, if there hasn't
+    /// been a run yet the image is returned unchanged
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The width of the returned image
+    /// * `height` - The height of the returned image
+    pub fn render_quantized(&self, width: u32, height: u32) -> Vec<u8> {
+        let clusters = match self.latest_result() {
+            Some(result) if !result.clusters.is_empty() => &result.clusters,
+            _ => return self.rgba_with(width, height, |color| color.clone()),
+        };
+        let (space, metric) = self.results_measure();
+        let assign = nearest_cluster_in(clusters, space, metric);
+        let mut nearest: BTreeMap<&Color, Color> = BTreeMap::new();
+
+        self.rgba_with(width, height, |color| {
+            nearest
+                .entry(color)
+                .or_insert_with(|| clusters[assign(color).0].clone())
+                .clone()
+        })
+    }
+
+    /// Returns a PNG of the clusters in the latest `RunResult` as a row of
+    /// square swatches, ready to be offered as a download through a `Blob`
+    /// with no canvas needed. If there hasn't been a run yet, or `swatch_size`
+    /// is 0, the returned array is empty
+    ///
+    /// # Arguments
+    ///
+    /// * `swatch_size` - The width and height in pixels of each swatch
+    pub fn palette_png(&self, swatch_size: u32) -> Vec<u8> {
+        let clusters = match self.latest_result() {
+            Some(result) if !result.clusters.is_empty() && swatch_size > 0 => &result.clusters,
+            _ => return vec![],
+        };
+        let row: Vec<u8> = clusters
+            .iter()
+            .flat_map(|c| {
+                std::iter::repeat_n([c.r as u8, c.g as u8, c.b as u8], swatch_size as usize)
+            })
+            .flatten()
+            .collect();
+        let rgb = row.repeat(swatch_size as usize);
+
+        png::encode_rgb(&rgb, clusters.len() as u32 * swatch_size, swatch_size)
+    }
+
+    /// Recolor the image as `render_quantized` does and draw it onto a canvas
+    /// at its origin
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The canvas 2d rendering context to draw to
+    #[cfg(feature = "web")]
+    pub fn apply_to_context(&self, ctx: &CanvasRenderingContext2d) {
+        let data = self.render_quantized(self.width, self.height);
+        let image_data =
+            ImageData::new_with_u8_clamped_array_and_sh(Clamped(&data), self.width, self.height)
+                .unwrap();
+
+        ctx.put_image_data(&image_data, 0.0, 0.0).unwrap();
+    }
+}
+
+impl ImageKmeans {
+    /// An instance with no image and nothing run
+    fn blank() -> ImageKmeans {
+        ImageKmeans {
+            colors: vec![],
+            alpha: vec![],
+            sample_rate: 1.0,
+            width: 0,
+            height: 0,
+            added_counts: BTreeMap::new(),
+            pixel_weights: None,
+            quantize_fact: DEFAULT_QUANTIZE_FACT,
+            working_colors: vec![],
+            working_colors_counts: BTreeMap::new(),
+            working_colors_weights: BTreeMap::new(),
+            working_points: vec![],
+            jittered_points: vec![],
+            initial_ks: vec![],
+            results: vec![],
+            selected_result: 0,
+            selection_detail: None,
+            results_config: Config::default(),
+            random: Box::new(rand::thread_rng()),
+            run_cache: VecDeque::new(),
+            histogram: None,
+            timing: RunTiming::default(),
+        }
+    }
+
+    /// An instance with no image at all, for tests to fill with
+    /// `set_working_colors_direct`. Only available with the `test-utils`
+    /// feature
+    #[cfg(feature = "test-utils")]
+    pub fn new_empty() -> ImageKmeans {
+        ImageKmeans::blank()
+    }
+
+    /// Cluster exactly the given colors with the given counts in place of an
+    /// image's pixels, so runs and the init methods can be tested on a known
+    /// working set. Every run builds its working colors from this set as it
+    /// would from an image, replacing any colors of the image and of images
+    /// added with `add_rgba`. Colors without a count are counted once. Only
+    /// available with the `test-utils` feature
+    ///
+    /// # Arguments
+    /// * `colors` - The working colors, in the order to use until the next run
+    /// * `counts` - The pixel count of each color
+    #[cfg(feature = "test-utils")]
+    pub fn set_working_colors_direct(
+        &mut self,
+        colors: Vec<Color>,
+        counts: BTreeMap<Color, usize>,
+    ) {
+        let count = |c: &Color| counts.get(c).copied().unwrap_or(1);
+        self.colors = vec![];
+        self.alpha = vec![];
+        self.pixel_weights = None;
+        (self.width, self.height) = (0, 0);
+        self.added_counts = colors.iter().map(|c| (c.clone(), count(c))).collect();
+        self.run_cache.clear();
+        self.histogram = None;
+
+        self.working_colors_counts = colors.iter().map(|c| (c.clone(), count(c))).collect();
+        self.working_colors_weights = colors
+            .iter()
+            .map(|c| (c.clone(), count(c) as f64))
+            .collect();
+        self.working_points = colors.iter().map(|c| ColorSpace::Rgb.to_point(c)).collect();
+        self.jittered_points = vec![];
+        self.working_colors = colors;
+    }
+
+    /// Replace the source of randomness used to pick initial clusters when no
+    /// `seed` is configured, by default this is the thread local generator
+    ///
+    /// # Arguments
+    /// * `source` - The new source of randomness
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource>) {
+        self.random = source;
+    }
+
+    /// The width and height of the stored image. These are the dimensions
+    /// after any sampling by `max_colors` or downsampling, so they match the
+    /// scan order of per-pixel outputs such as `error_map`, where pixel `i`
+    /// is at `(i % width, i / width)`
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The color of the stored image at a position, `None` when the position
+    /// is outside of `dimensions`. Images added with `add_rgba` aren't included
+    ///
+    /// # Arguments
+    /// * `x` - The column, from `0` on the left
+    /// * `y` - The row, from `0` at the top
+    pub fn pixel_at(&self, x: u32, y: u32) -> Option<Color> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let index = y as usize * self.width as usize + x as usize;
+        self.colors.get(index).cloned()
+    }
+
+    /// The colors the latest run clustered, after quantizing, merging and any
+    /// `top_num` style limit, most frequent first. Before any run these are
+    /// the image's colors with the default config. Any `apply_gamma` or
+    /// `saturation_scale` adjustment is included
+    pub fn working_colors(&self) -> Vec<Color> {
+        self.working_colors.clone()
+    }
+
+    /// The colors of `working_colors`, in the same order, with how many
+    /// pixels each stands for
+    pub fn working_color_counts(&self) -> Vec<(Color, usize)> {
+        self.working_colors
+            .iter()
+            .map(|c| (c.clone(), self.working_colors_counts[c]))
+            .collect()
+    }
+
+    /// Count every color in the image as it is, without the quantizing,
+    /// merging or limiting applied to the working colors of a run. Colors of
+    /// images added with `add_rgba` are included
+    pub fn raw_histogram(&self) -> BTreeMap<Color, usize> {
+        let mut counts = self.added_counts.clone();
+        for color in &self.colors {
+            *counts.entry(color.clone()).or_insert(0) += 1;
+        }
+
+        counts
+    }
+
+    /// Run the same `k` once with `InitMethod::Random` and once with
+    /// `InitMethod::KmeansPlusPlus`, otherwise with the same config and so
+    /// the same seed when one is set, and return both results in that order.
+    /// Both runs are left in the result set with the one of lower WCSS
+    /// selected, the k-means++ run on a tie
+    ///
+    /// # Arguments
+    /// * `k` - The number of `k` clusters to use for both runs
+    /// * `config` - Optional `Config` for both runs, its `init` is ignored
+    pub fn compare_inits(
+        &mut self,
+        k: usize,
+        config: Option<Config>,
+    ) -> Result<(RunResult, RunResult), KmeansError> {
+        let config = config.unwrap_or_default();
+        let random = self.with_fixed_k_number(
+            k,
+            Some(Config {
+                init: InitMethod::Random,
+                ..config.clone()
+            }),
+        )?;
+        let plus_plus = self.with_fixed_k_number(
+            k,
+            Some(Config {
+                init: InitMethod::KmeansPlusPlus,
+                ..config
+            }),
+        )?;
+
+        self.results = vec![random.clone(), plus_plus.clone()];
+        self.selected_result = (plus_plus.wcss <= random.wcss) as usize;
+        Ok((random, plus_plus))
+    }
+
+    /// Performs the runs of `with_derived_k_number`, calling `cancel` between
+    /// each run and each k-means iteration and stopping with a
+    /// `KmeansError::Cancelled` as soon as it returns `true`. The runs completed
+    /// before cancelling are left in the result set
+    ///
+    /// # Arguments
+    /// * `config` - Optional `Config` applied to every run
+    /// * `cancel` - Returns whether to stop
+    pub fn with_derived_k_number_cancellable(
+        &mut self,
+        config: Option<Config>,
+        mut cancel: impl FnMut() -> bool,
+    ) -> Result<RunResult, KmeansError> {
+        let config = config.unwrap_or_default();
+        config.validate()?;
+        config.validate_k(10)?;
+        self.check_can_run()?;
+        self.set_working_colors(&config)?;
+        self.results = vec![];
+        self.results_config = config.clone();
+
+        // a single color has only one sensible clustering, there's no curve
+        // to find an elbow in
+        if self.working_colors.len() == 1 {
+            self.results = vec![self.fresh_run(1, &config, &mut cancel)?];
+            self.selected_result = 0;
+            self.selection_detail = Some(SelectionDetail {
+                distances: vec![0.0],
+                chosen_k: 1,
+                chosen_distance: 0.0,
+            });
+            return Ok(self.results[0].clone());
+        }
+
+        let max_ks = self.use_initial_ks(10, &config);
+        let mut plateau = 0;
+
+        for i in 1..=max_ks {
+            let run = if config.grayscale_split.is_some() {
+                self.fresh_run(i, &config, &mut cancel)
+            } else {
+                self.do_run(i, &config, &mut cancel)
+            };
+            match run {
+                Ok(result) => self.results.push(result),
+                Err(err) => {
+                    self.selected_result = self.results.len().saturating_sub(1);
+                    self.selection_detail = None;
+                    return Err(err);
+                }
+            }
+
+            if let (Some(threshold), [.., previous, latest]) =
+                (config.plateau_threshold, &self.results[..])
+            {
+                let improvement = if previous.wcss > f32::EPSILON {
+                    (previous.wcss - latest.wcss) / previous.wcss
+                } else {
+                    0.0
+                };
+                plateau = if improvement < threshold {
+                    plateau + 1
+                } else {
+                    0
+                };
+                if plateau == PLATEAU_STEPS {
+                    break;
+                }
+            }
+        }
+        let stopped_early = self.results.len() < max_ks;
+
+        let wcss = self.results.iter().map(|r| r.wcss).collect::<Vec<f32>>();
+        if let KSelection::Kneedle { sensitivity } = config.k_selection {
+            let differences = difference_curve(&wcss);
+            let index = if wcss.iter().all(|w| *w <= f32::EPSILON) {
+                0
+            } else {
+                find_knee(&differences, sensitivity).unwrap_or(0)
+            };
+            return Ok(self.select_result(index, differences));
+        }
+
+        let curve = if config.normalize_elbow {
+            normalized_curve(&wcss)
+        } else {
+            wcss.iter()
+                .enumerate()
+                .map(|(i, &w)| ((i + 1) as f32, w))
+                .collect()
+        };
+
+        let (x1, y1) = curve[0];
+        let (x2, y2) = if config.normalize_elbow || stopped_early {
+            curve[curve.len() - 1]
+        } else {
+            (11.0, wcss[wcss.len() - 1])
+        };
+
+        let mut distances: Vec<f32> = vec![];
+
+        for &(x0, y0) in &curve {
+            let num = f32::abs((y2 - y1) * x0 - (x2 - x1) * y0 + x2 * y1 - y2 * x1);
+            let denum = f32::sqrt(f32::powi(y2 - y1, 2) + f32::powi(x2 - x1, 2));
+            distances.push(num / denum);
+        }
+
+        let max_dist = distances.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+        let max_index = if wcss.iter().all(|w| *w <= f32::EPSILON) {
+            0
+        } else {
+            distances.iter().position(|&r| r == max_dist).unwrap()
+        };
+
+        Ok(self.select_result(max_index, distances))
+    }
+
+    /// Select one of the results of a derived run, recording why it was chosen
+    /// in `ImageKmeans.selection_detail`, and return it
+    ///
+    /// # Arguments
+    /// * `index` - The index of the chosen result
+    /// * `distances` - The score of every result that it was chosen by
+    fn select_result(&mut self, index: usize, distances: Vec<f32>) -> RunResult {
+        self.selected_result = index;
+        self.selection_detail = Some(SelectionDetail {
+            chosen_k: self.results[index].ks,
+            chosen_distance: distances[index],
+            distances,
+        });
+
+        self.results[index].clone()
+    }
+
+    /// Recolor other images with the clusters of the latest `RunResult`, as
+    /// `recolor_images` does from JS
+    ///
+    /// # Arguments
+    /// * `frames` - The pixel data of each image, 4 bytes per pixel in RGBA order
+    /// * `width` - The width of every image
+    /// * `height` - the height of every image
+    pub fn recolor_frames(
+        &self,
+        frames: &[&[u8]],
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<Vec<u8>>, KmeansError> {
+        let clusters = match self.latest_result() {
+            Some(result) if !result.clusters.is_empty() => &result.clusters,
+            _ => return Err(KmeansError::NoRun),
+        };
+        let (space, metric) = self.results_measure();
+        let assign = nearest_cluster_in(clusters, space, metric);
+        let mut nearest: BTreeMap<Color, Color> = BTreeMap::new();
+
+        frames
+            .iter()
+            .map(|frame| {
+                let colors = parse_rgba(frame, width, height)?;
+                Ok(colors
+                    .into_iter()
+                    .zip(frame.chunks_exact(4))
+                    .flat_map(|(color, px)| {
+                        let c = nearest
+                            .entry(color)
+                            .or_insert_with_key(|c| clusters[assign(c).0].clone());
+                        [c.r as u8, c.g as u8, c.b as u8, px[3]]
+                    })
+                    .collect())
+            })
+            .collect()
+    }
+
+    /// Iterate over an entry for each cluster of the latest `RunResult`, as
+    /// `palette_json_with_counts` returns them, building each only as it's
+    /// taken. Yields nothing if there hasn't been a run yet
+    pub fn palette_iter(&self) -> impl Iterator<Item = PaletteEntry> + '_ {
+        let result = self.latest_result();
+        let total: usize = result.map_or(0, |r| r.counts.iter().sum());
+
+        result
+            .into_iter()
+            .flat_map(|r| r.clusters.iter().zip(&r.counts))
+            .enumerate()
+            .map(move |(index, (color, &count))| PaletteEntry {
+                index,
+                color: color.clone(),
+                hex: color.to_hex(),
+                count,
+                fraction: if total > 0 {
+                    count as f32 / total as f32
+                } else {
+                    0.0
+                },
+            })
+    }
+
+    /// The clusters of the latest `RunResult` with their counts, highest
+    /// scoring first by a score of the caller's own. Clusters scoring the
+    /// same keep their order in the result. Empty if there hasn't been a run
+    /// yet
+    ///
+    /// # Arguments
+    /// * `score` - Scores a cluster from its color and count
+    pub fn palette_ranked_by<F: Fn(&Color, usize) -> f32>(&self, score: F) -> Vec<(Color, usize)> {
+        let Some(result) = self.latest_result() else {
+            return vec![];
+        };
+        let mut ranked: Vec<(f32, Color, usize)> = result
+            .clusters
+            .iter()
+            .zip(&result.counts)
+            .map(|(color, &count)| (score(color, count), color.clone(), count))
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        ranked
+            .into_iter()
+            .map(|(_, color, count)| (color, count))
+            .collect()
+    }
+
+    /// The clusters of the latest run with the most and the fewest pixels
+    /// nearest to them, as `(dominant, rare)`. Ties go to the earliest
+    /// cluster, so with a single cluster both are the same color. `None` if
+    /// there hasn't been a run yet
+    pub fn dominant_and_rare(&self) -> Option<(Color, Color)> {
+        let result = self.latest_result()?;
+        let mut dominant = 0;
+        let mut rare = 0;
+
+        for (i, &count) in result.counts.iter().enumerate() {
+            if count > result.counts[dominant] {
+                dominant = i;
+            }
+            if count < result.counts[rare] {
+                rare = i;
+            }
+        }
+
+        Some((
+            result.clusters.get(dominant)?.clone(),
+            result.clusters.get(rare)?.clone(),
+        ))
+    }
+
+    /// Score an externally chosen palette against the image, giving the WCSS
+    /// from assigning every working color to its nearest palette color without
+    /// running any iterations. The working colors are those of the latest run,
+    /// or all of the image's colors if there hasn't been one
+    ///
+    /// Returns a `KmeansError::InvalidConfig` if a palette color has a
+    /// channel out of range
+    ///
+    /// # Arguments
+    /// * `palette` - The colors to score, an empty palette scores `0.0`
+    pub fn score_palette(&self, palette: &[Color]) -> Result<f32, KmeansError> {
+        check_channels(palette, "palette")?;
+        Ok(self.palette_wcss(palette, ColorSpace::Rgb, DistanceMetric::Euclidean))
+    }
+
+    /// Map the image onto a fixed reference palette, such as `Color::web_safe`,
+    /// assigning every working color to its nearest palette color in a single
+    /// pass with no iterations. The result has the palette colors that were
+    /// used, in the palette's order, with their `counts` and the WCSS. The
+    /// working colors are those of the latest run, or all of the image's
+    /// colors if there hasn't been one. Returns a `KmeansError::InvalidConfig`
+    /// if a palette color has a channel out of range
+    ///
+    /// # Arguments
+    /// * `palette` - The reference colors to map to
+    pub fn quantize_to_palette(&self, palette: &[Color]) -> Result<RunResult, KmeansError> {
+        check_channels(palette, "palette")?;
+        Ok(self.palette_result(palette))
+    }
+
+    /// Label each cluster of the latest `RunResult` with the nearest color in
+    /// a table of named colors, such as the CSS named colors. Colors are
+    /// compared by their Lab delta-E so the names match how the colors look.
+    /// Gives, for each cluster in order, the name, the named color and the
+    /// delta-E to it. Empty if there hasn't been a run yet or the table is
+    /// empty
+    ///
+    /// Returns a `KmeansError::InvalidConfig` if a named color has a channel
+    /// out of range
+    ///
+    /// # Arguments
+    /// * `named` - The names and their colors to pick from
+    pub fn name_palette(
+        &self,
+        named: &[(String, Color)],
+    ) -> Result<Vec<(String, Color, f32)>, KmeansError> {
+        let colors: Vec<Color> = named.iter().map(|(_, c)| c.clone()).collect();
+        check_channels(&colors, "named")?;
+        let Some(result) = self.latest_result().filter(|_| !named.is_empty()) else {
+            return Ok(vec![]);
+        };
+
+        let space = ColorSpace::Lch;
+        let points: Vec<Point> = colors.iter().map(|c| space.to_point(c)).collect();
+        Ok(result
+            .clusters
+            .iter()
+            .map(|cluster| {
+                let (i, distance) = space.nearest(&space.to_point(cluster), &points);
+                (named[i].0.clone(), colors[i].clone(), distance)
+            })
+            .collect())
+    }
+
+    /// The result of `quantize_to_palette` for a palette known to be in range
+    ///
+    /// # Arguments
+    /// * `palette` - The reference colors to map to
+    fn palette_result(&self, palette: &[Color]) -> RunResult {
+        let mut counts = vec![0; palette.len()];
+        if !palette.is_empty() {
+            for color in &self.working_colors {
+                let (nearest, _) = self.nearest_cluster(color, palette);
+                counts[nearest] += self.working_colors_counts[color];
+            }
+        }
+
+        let (clusters, counts): (Vec<Color>, Vec<usize>) = palette
+            .iter()
+            .cloned()
+            .zip(counts)
+            .filter(|&(_, count)| count > 0)
+            .unzip();
+
+        RunResult {
+            ks: clusters.len(),
+            distinct: count_distinct(&clusters),
+            wcss: self.palette_wcss(&clusters, ColorSpace::Rgb, DistanceMetric::Euclidean),
+            counts,
+            variance_explained: self.variance_explained(
+                &clusters,
+                ColorSpace::Rgb,
+                DistanceMetric::Euclidean,
+            ),
+            convergence: vec![],
+            float_centroids: None,
+            iterations: 1,
+            clusters,
+            trace: None,
+            centroid_history: None,
+            timing: None,
+            warnings: vec![],
+        }
+    }
+
+    /// The WCSS of a palette over the working colors as `score_palette` gives
+    /// it, with the distances measured in the given color space
+    ///
+    /// # Arguments
+    /// * `palette` - The colors to score, an empty palette scores `0.0`
+    /// * `space` - The color space to measure distances in
+    /// * `metric` - How distances are measured in `space`
+    fn palette_wcss(&self, palette: &[Color], space: ColorSpace, metric: DistanceMetric) -> f32 {
+        if palette.is_empty() {
+            return 0.0;
+        }
+        let palette: Vec<Point> = palette.iter().map(|c| space.to_point(c)).collect();
+
+        self.working_colors
+            .iter()
+            .map(|color| {
+                let point = space.to_point(color);
+                let (nearest, _) = metric.nearest(space, &point, &palette);
+                metric.squared_distance(space, &point, &palette[nearest])
+                    * self.working_colors_weights[color]
+            })
+            .sum::<f64>() as f32
+    }
+
+    /// Each cluster's share of the variance explained over the working colors,
+    /// see `RunResult.variance_explained`
+    ///
+    /// # Arguments
+    /// * `clusters` - The clusters to share the variance between
+    /// * `space` - The color space to measure distances in
+    /// * `metric` - How distances are measured in `space`
+    fn variance_explained(
+        &self,
+        clusters: &[Color],
+        space: ColorSpace,
+        metric: DistanceMetric,
+    ) -> Vec<f32> {
+        let points: Vec<(Point, f64)> = self
+            .working_colors
+            .iter()
+            .map(|color| (space.to_point(color), self.working_colors_weights[color]))
+            .collect();
+        let clusters: Vec<Point> = clusters.iter().map(|k| space.to_point(k)).collect();
+
+        variance_shares(&points, &clusters, space, metric)
+    }
+
+    /// The unrounded mean of the working colors nearest each cluster, as RGB
+    /// channels. Locked clusters, and clusters no color is nearest to, give
+    /// their own color
+    ///
+    /// # Arguments
+    /// * `clusters` - The clusters to average the colors of
+    /// * `locked` - How many of the leading `clusters` are locked colors
+    /// * `space` - The color space of `ImageKmeans.working_points`
+    /// * `metric` - How distances are measured in `space`
+    fn float_means(
+        &self,
+        clusters: &[Color],
+        locked: usize,
+        space: ColorSpace,
+        metric: DistanceMetric,
+    ) -> Vec<[f32; 3]> {
+        let cluster_points: Vec<Point> = clusters.iter().map(|k| space.to_point(k)).collect();
+        let mut members: Vec<Vec<usize>> = vec![vec![]; clusters.len()];
+        for (i, point) in self.working_points.iter().enumerate() {
+            members[metric.nearest(space, point, &cluster_points).0].push(i);
+        }
+
+        clusters
+            .iter()
+            .zip(members)
+            .enumerate()
+            .map(|(i, (cluster, members))| {
+                if i < locked || members.is_empty() {
+                    return [cluster.r as f32, cluster.g as f32, cluster.b as f32];
+                }
+                space.to_rgb(space.weighted_mean(members.iter().map(|&m| {
+                    let weight = self.working_colors_weights[&self.working_colors[m]];
+                    (&self.working_points[m], weight)
+                })))
+            })
+            .collect()
+    }
+
+    /// The color space and distance metric of `ImageKmeans.results`
+    fn results_measure(&self) -> (ColorSpace, DistanceMetric) {
+        (
+            self.results_config.color_space,
+            self.results_config.distance_metric,
+        )
+    }
+
+    /// The latest `RunResult` returned to the caller, for a derived run this is
+    /// the chosen result rather than the last one calculated
+    fn latest_result(&self) -> Option<&RunResult> {
+        self.results.get(self.selected_result)
+    }
+
+    /// Build RGBA pixel data for the image scaled to `width` x `height` by
+    /// nearest neighbour, mapping each pixel's color and keeping its original
+    /// alpha
+    ///
+    /// # Arguments
+    /// * `width` - The width of the built image
+    /// * `height` - The height of the built image
+    /// * `map` - gives the color to write for each pixel's color
+    fn rgba_with<'a>(
+        &'a self,
+        width: u32,
+        height: u32,
+        mut map: impl FnMut(&'a Color) -> Color,
+    ) -> Vec<u8> {
+        let source = |x: u32, y: u32| {
+            let sx = x as u64 * self.width as u64 / width as u64;
+            let sy = y as u64 * self.height as u64 / height as u64;
+            (sy * self.width as u64 + sx) as usize
+        };
+
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let i = source(x, y);
+                let c = map(&self.colors[i]);
+                [c.r as u8, c.g as u8, c.b as u8, self.alpha[i]]
+            })
+            .collect()
+    }
+
+    /// Find a remembered run for exactly this `k` and config, marking it as
+    /// the most recently used. Unseeded runs are never remembered
+    ///
+    /// # Arguments
+    /// * `k_number` - The number of `k` clusters for the run
+    /// * `config` - The `Config` for the run
+    fn cached_run(&mut self, k_number: usize, config: &Config) -> Option<RunResult> {
+        let index = self
+            .run_cache
+            .iter()
+            .position(|(k, c, _)| *k == k_number && c == config)?;
+        let entry = self.run_cache.remove(index)?;
+        let result = entry.2.clone();
+        self.run_cache.push_back(entry);
+
+        Some(result)
+    }
+
+    /// Check there is something to cluster before starting a run
+    fn check_can_run(&self) -> Result<(), KmeansError> {
+        if self.colors.is_empty() && self.added_counts.is_empty() {
+            return Err(KmeansError::NoColors);
+        }
+        Ok(())
+    }
+
+    /// Build the histogram of colors the clustering works on from the image
+    /// pixels, setting `ImageKmeans.working_colors` and their counts in
+    /// `ImageKmeans.working_colors_counts`, with their weights in
+    /// `ImageKmeans.working_colors_weights`. Each working color is also
+    /// converted to the configured color space in `ImageKmeans.working_points`
+    ///
+    /// When `merge_delta_e` is configured near identical colors are merged
+    /// before any `top_num` or `top_percent` limit is applied. Quantizing
+    /// happens first unless `quantize_stage` puts it after the limit.
+    ///
+    /// The working colors are ordered by frequency, most frequent first, with
+    /// ties broken by the color itself. Clusters are assigned iterating in this
+    /// order so the assignment is order-stable from run to run. When `top_num`
+    /// or `top_percent` are configured only that many of them are kept, along
+    /// with any `top_chromatic` accents
+    ///
+    /// Each pixel counts according to the configured `alpha_mode`, colors
+    /// with no count at all are left out. Returns `KmeansError::NoColors` when
+    /// that leaves nothing to cluster
+    ///
+    /// # Arguments
+    /// * `config` - The `Config` for the upcoming run
+    fn set_working_colors(&mut self, config: &Config) -> Result<(), KmeansError> {
+        let start = now_ms();
+        self.quantize_fact = match config.target_distinct {
+            Some(target) => self.fact_for_distinct(target),
+            None => config.quantize_fact.unwrap_or(DEFAULT_QUANTIZE_FACT),
+        };
+        let fact = self.quantize_fact;
+        let count_fact = match config.quantize_stage {
+            QuantizeStage::BeforeTopN => fact,
+            QuantizeStage::AfterTopN => 1,
+        };
+
+        // the full histogram only changes with these options, so moving a
+        // top_num or top_percent limit just takes a different prefix of it
+        let key = HistogramKey {
+            fact: count_fact,
+            coherence_weight: config.coherence_weight,
+            merge_delta_e: config.merge_delta_e,
+            alpha_mode: config.alpha_mode,
+            region_weighting: config.region_weighting,
+        };
+        if self
+            .histogram
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != key)
+        {
+            self.histogram = Some((key, self.sorted_histogram(config, count_fact)));
+        }
+        let histogram = &self.histogram.as_ref().unwrap().1;
+
+        let keep = match (config.top_num, config.top_percent) {
+            (Some(num), _) => num,
+            (None, Some(pct)) => ((histogram.len() as f32 * pct).ceil() as usize).max(1),
+            (None, None) => histogram.len(),
+        };
+        let (top, rest) = histogram.split_at(keep.min(histogram.len()));
+        let mut sorted = top.to_vec();
+
+        if let Some(num) = config.top_chromatic {
+            let chroma = |c: &Color| ColorSpace::Lch.to_point(c)[1];
+            let mut accents: Vec<&HistogramEntry> = rest.iter().collect();
+            // stable so equally chromatic colors keep their frequency order
+            accents.sort_by(|a, b| chroma(&b.0).total_cmp(&chroma(&a.0)));
+            sorted.extend(accents.into_iter().take(num).cloned());
+            sorted.sort_by(by_frequency);
+        }
+
+        if count_fact != fact {
+            let mut quantized: BTreeMap<Color, (usize, f64)> = BTreeMap::new();
+            for (color, count, weight) in sorted {
+                let entry = quantized.entry(color.quantized(fact)).or_insert((0, 0.0));
+                entry.0 += count;
+                entry.1 += weight;
+            }
+            sorted = quantized.into_iter().map(|(c, (n, w))| (c, n, w)).collect();
+            sorted.sort_by(by_frequency);
+        }
+
+        if config.saturation_scale.is_some() || config.apply_gamma.is_some() {
+            let mut adjusted: BTreeMap<Color, (usize, f64)> = BTreeMap::new();
+            for (mut color, count, weight) in sorted {
+                if let Some(scale) = config.saturation_scale {
+                    color = color.saturated(scale);
+                }
+                if let Some(gamma) = config.apply_gamma {
+                    color = color.with_gamma(gamma);
+                }
+                let entry = adjusted.entry(color).or_insert((0, 0.0));
+                entry.0 += count;
+                entry.1 += weight;
+            }
+            sorted = adjusted.into_iter().map(|(c, (n, w))| (c, n, w)).collect();
+            sorted.sort_by(by_frequency);
+        }
+
+        if let Some(max) = config.max_working_colors {
+            fold_tail(&mut sorted, max, config.color_space);
+        }
+
+        self.working_colors = sorted.iter().map(|(c, _, _)| c.clone()).collect();
+        self.working_colors_counts = sorted.iter().map(|(c, n, _)| (c.clone(), *n)).collect();
+        self.working_colors_weights = sorted.into_iter().map(|(c, _, w)| (c, w)).collect();
+        self.working_points = self
+            .working_colors
+            .iter()
+            .map(|c| config.color_space.to_point(c))
+            .collect();
+        self.jittered_points = match config.jitter {
+            Some(sigma) => {
+                random::gaussian_jitter(&self.working_points, sigma, config.seed.unwrap_or(0))
+            }
+            None => vec![],
+        };
+        self.timing.working_colors_ms = now_ms() - start;
+
+        if self.working_colors.is_empty() {
+            return Err(KmeansError::NoColors);
+        }
+        Ok(())
+    }
+
+    /// Count every pixel's color, quantized by `fact`, along with the colors of
+    /// any added images and merge them as configured. Each count comes with
+    /// the total weight of the pixels counted, scaled by any pixel weights.
+    /// Returned most frequent first as `ImageKmeans.working_colors` are ordered
+    ///
+    /// # Arguments
+    /// * `config` - The `Config` for the upcoming run
+    /// * `fact` - The quantization factor to count colors with
+    fn sorted_histogram(&self, config: &Config, fact: i32) -> Vec<HistogramEntry> {
+        let width = self.width as usize;
+        let mut counts: BTreeMap<Color, (usize, f64)> = BTreeMap::new();
+        let mut counted = vec![false; self.colors.len()];
+        let colors: Vec<Color> = self
+            .colors
+            .iter()
+            .zip(&self.alpha)
+            .map(|(c, &alpha)| config.alpha_mode.pixel_color(c, alpha).quantized(fact))
+            .collect();
+
+        for (i, color) in colors.iter().enumerate() {
+            let weight = config.alpha_mode.pixel_count(self.alpha[i]);
+            let pixel_weight = self.pixel_weights.as_ref().map_or(1.0, |w| w[i] as f64);
+            if weight == 0 || pixel_weight == 0.0 {
+                continue;
+            }
+            counted[i] = true;
+            let entry = counts.entry(color.clone()).or_insert((0, 0.0));
+            entry.0 += weight;
+            entry.1 += weight as f64 * pixel_weight;
+
+            if config.coherence_weight > 0 {
+                let (x, y) = (i % width, i / width);
+                let neighbours = [
+                    (x > 0).then(|| i - 1),
+                    (x + 1 < width).then(|| i + 1),
+                    (y > 0).then(|| i - width),
+                    (y + 1 < self.height as usize).then(|| i + width),
+                ];
+                let same = neighbours
+                    .iter()
+                    .flatten()
+                    .filter(|&&n| colors[n] == *color)
+                    .count();
+                let extra = same * config.coherence_weight * weight;
+                let entry = counts.get_mut(color).unwrap();
+                entry.0 += extra;
+                entry.1 += extra as f64 * pixel_weight;
+            }
+        }
+
+        if config.region_weighting != RegionWeighting::Pixels && width > 0 {
+            let cells: Vec<Option<&Color>> = colors
+                .iter()
+                .zip(&counted)
+                .map(|(color, &counted)| counted.then_some(color))
+                .collect();
+            let mut by_regions: BTreeMap<&Color, usize> = BTreeMap::new();
+            for (i, size) in regions::regions(&cells, width) {
+                let entry = by_regions.entry(&colors[i]).or_insert(0);
+                *entry = match config.region_weighting {
+                    RegionWeighting::LargestRegion => (*entry).max(size),
+                    _ => *entry + 1,
+                };
+            }
+            for (color, region_count) in by_regions {
+                let entry = counts.get_mut(color).unwrap();
+                entry.1 *= region_count as f64 / entry.0 as f64;
+                entry.0 = region_count;
+            }
+        }
+
+        for (color, count) in &self.added_counts {
+            let entry = counts.entry(color.quantized(fact)).or_insert((0, 0.0));
+            entry.0 += count;
+            entry.1 += *count as f64;
+        }
+
+        let mut sorted: Vec<HistogramEntry> =
+            counts.into_iter().map(|(c, (n, w))| (c, n, w)).collect();
+        sorted.sort_by(by_frequency);
+
+        if let Some(delta_e) = config.merge_delta_e {
+            sorted = merge_similar_colors(sorted, delta_e);
+            sorted.sort_by(by_frequency);
+        }
+
+        sorted
+    }
+
+    /// Binary search for the smallest quantization factor leaving at most
+    /// `target` distinct colors
+    ///
+    /// # Arguments
+    /// * `target` - The most distinct colors wanted, at least 1
+    fn fact_for_distinct(&self, target: usize) -> i32 {
+        let distinct = |fact: i32| {
+            self.colors
+                .iter()
+                .chain(self.added_counts.keys())
+                .map(|c| c.quantized(fact))
+                .collect::<HashSet<Color>>()
+                .len()
+        };
+        let (mut low, mut high) = (1, 256);
+
+        while low < high {
+            let mid = (low + high) / 2;
+            if distinct(mid) <= target {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        low
+    }
+
+    /// Pick the initial k clusters with the configured `InitMethod`, setting
+    /// them as `ImageKmeans.initial_ks`. Returns the number of colors actually
+    /// picked, which is fewer than `a` when the image doesn't have that many
+    /// distinct colors
+    ///
+    /// # Arguments
+    /// * `a` - The number of colors to pick for our initial k clusters
+    /// * `config` - The `Config` for the run
+    fn use_initial_ks(&mut self, a: usize, config: &Config) -> usize {
+        let start = now_ms();
+        let picked = match config.init {
+            InitMethod::Random => self.use_random_ks(a, config),
+            InitMethod::KmeansPlusPlus => self.use_kmeans_plus_plus(a, config),
+            InitMethod::KmeansParallel => self.use_kmeans_parallel(a, config),
+            InitMethod::Deterministic => {
+                self.initial_ks = init::evenly_spaced(&self.working_weights(), a)
+                    .into_iter()
+                    .map(|i| self.working_colors[i].clone())
+                    .collect();
+                self.initial_ks.len()
+            }
+        };
+        self.timing.init_ms = now_ms() - start;
+
+        picked
+    }
+
+    /// Pick new initial clusters for `k` and run them, or with a
+    /// `grayscale_split` configured do the separate grey and chromatic runs
+    ///
+    /// # Arguments
+    /// * `k` - How many k clusters to run for
+    /// * `config` - The `Config` for the run
+    /// * `cancel` - Checked between iterations as in `do_run`
+    fn fresh_run(
+        &mut self,
+        k: usize,
+        config: &Config,
+        cancel: &mut dyn FnMut() -> bool,
+    ) -> Result<RunResult, KmeansError> {
+        let Some(split) = config.grayscale_split else {
+            let num_ks = self.use_initial_ks(k, config);
+            return self.do_run(num_ks, config, cancel);
+        };
+
+        let (grays, chromatic): (Vec<usize>, Vec<usize>) = (0..self.working_colors.len())
+            .partition(|&i| self.working_colors[i].is_grayscale(split.tolerance));
+        let gray_k = match (grays.is_empty(), chromatic.is_empty()) {
+            (true, _) => 0,
+            (_, true) => k,
+            _ if k < 2 => (k as f32 * split.grayscale_fraction).round() as usize,
+            _ => ((k as f32 * split.grayscale_fraction).round() as usize).clamp(1, k - 1),
+        };
+
+        let colors = std::mem::take(&mut self.working_colors);
+        let points = std::mem::take(&mut self.working_points);
+        let jittered = std::mem::take(&mut self.jittered_points);
+        let mut runs = vec![];
+        for (members, bucket_k) in [(grays, gray_k), (chromatic, k - gray_k)] {
+            if members.is_empty() || bucket_k == 0 {
+                continue;
+            }
+            self.working_colors = members.iter().map(|&i| colors[i].clone()).collect();
+            self.working_points = members.iter().map(|&i| points[i]).collect();
+            if !jittered.is_empty() {
+                self.jittered_points = members.iter().map(|&i| jittered[i]).collect();
+            }
+            let num_ks = self.use_initial_ks(bucket_k, config);
+            let run = self.do_run(num_ks, config, cancel);
+            let failed = run.is_err();
+            runs.push(run);
+            if failed {
+                break;
+            }
         }
+        self.working_colors = colors;
+        self.working_points = points;
+        self.jittered_points = jittered;
 
-        let colors: Vec<Color> = pixels.into_iter().unique().collect();
+        let mut result = RunResult {
+            ks: 0,
+            clusters: vec![],
+            wcss: 0.0,
+            distinct: 0,
+            counts: vec![],
+            variance_explained: vec![],
+            convergence: vec![],
+            float_centroids: None,
+            iterations: 0,
+            trace: None,
+            centroid_history: None,
+            timing: None,
+            warnings: vec![],
+        };
+        for run in runs {
+            let run = run?;
+            result.clusters.extend(run.clusters);
+            result.counts.extend(run.counts);
+            if let Some(centroids) = run.float_centroids {
+                result
+                    .float_centroids
+                    .get_or_insert_with(Vec::new)
+                    .extend(centroids);
+            }
+            result.wcss += run.wcss;
+            result.iterations = result.iterations.max(run.iterations);
+            // the run that stopped first keeps its final WCSS while the other
+            // carries on
+            let length = result.convergence.len().max(run.convergence.len());
+            let at = |curve: &[f32], i: usize| curve.get(i).or(curve.last()).copied();
+            result.convergence = (0..length)
+                .map(|i| {
+                    at(&result.convergence, i).unwrap_or(0.0)
+                        + at(&run.convergence, i).unwrap_or(0.0)
+                })
+                .collect();
+            // both runs share the working colors but pick and iterate apart
+            if let Some(timing) = run.timing {
+                result.timing = Some(match result.timing {
+                    Some(total) => RunTiming {
+                        init_ms: total.init_ms + timing.init_ms,
+                        iterations_ms: total.iterations_ms + timing.iterations_ms,
+                        ..total
+                    },
+                    None => timing,
+                });
+            }
+        }
+        result.ks = result.clusters.len();
+        result.distinct = count_distinct(&result.clusters);
+        // each run's shares are of its own colors, the palette shares all of them
+        let working_clusters: Vec<Color> = match config.apply_gamma {
+            Some(gamma) => result
+                .clusters
+                .iter()
+                .map(|c| c.with_gamma(gamma))
+                .collect(),
+            None => result.clusters.clone(),
+        };
+        result.variance_explained = self.variance_explained(
+            &working_clusters,
+            config.color_space,
+            config.distance_metric,
+        );
 
-        ImageKmeans {
-            colors,
-            initial_ks: vec![],
-            results: vec![],
+        if config.sort_order == SortOrder::FirstAppearance {
+            let order = self.first_appearances(
+                &result.clusters,
+                config.color_space,
+                config.distance_metric,
+            );
+            result.clusters = order.iter().map(|&i| result.clusters[i].clone()).collect();
+            result.counts = order.iter().map(|&i| result.counts[i]).collect();
+            result.variance_explained = order
+                .iter()
+                .map(|&i| result.variance_explained[i])
+                .collect();
+            result.float_centroids = result
+                .float_centroids
+                .map(|c| order.iter().map(|&i| c[i]).collect());
         }
+        Ok(result)
     }
 
-    /// Returns the ResultSet from the current run to JS as an array of `RunResult`s,
-    /// in the case where no run has happend yet an empty array will be returned
+    /// Take a random number of colors from the complete list of the given image
+    /// and set these as `ImageKmeans.initial_ks`. Returns the number of colors
+    /// actually picked
     ///
-    /// @deprecated
-    pub fn get_result_set(&self) -> JsValue {
-        JsValue::from_serde(&self.results).unwrap()
+    /// # Arguments
+    /// * `a` - The number of random colors to pick for our initial k clusters
+    /// * `config` - The `Config` for the run, if it has a `seed` this is used
+    ///   in place of the instance's random source
+    fn use_random_ks(&mut self, a: usize, config: &Config) -> usize {
+        self.initial_ks = random::with_source(config.seed, self.random.as_mut(), |rng| {
+            random::pick_distinct(&self.working_colors, a, rng)
+        });
+        self.initial_ks.len()
     }
 
-    /// Do a run with a fixed number of `k` clusters and return the result set to JS
-    /// as a single `RunResult`
+    /// Pick the initial clusters with k-means++ and set these as
+    /// `ImageKmeans.initial_ks`. Returns the number of colors actually picked
     ///
     /// # Arguments
+    /// * `a` - The number of colors to pick for our initial k clusters
+    /// * `config` - The `Config` for the run
+    fn use_kmeans_plus_plus(&mut self, a: usize, config: &Config) -> usize {
+        let weights = self.working_weights();
+        let picks = random::with_source(config.seed, self.random.as_mut(), |rng| {
+            init::kmeans_plus_plus(
+                &self.working_points,
+                &weights,
+                config.color_space,
+                config.distance_metric,
+                a,
+                vec![],
+                rng,
+            )
+        });
+
+        self.initial_ks = picks
+            .into_iter()
+            .map(|i| self.working_colors[i].clone())
+            .collect();
+        self.initial_ks.len()
+    }
+
+    /// Pick the initial clusters with k-means|| and set these as
+    /// `ImageKmeans.initial_ks`. Returns the number of colors actually picked
     ///
-    /// * `k_number` - The number of `k` clusters to use for this run
-    pub fn with_fixed_k_number(&mut self, k_number: usize) -> RunResult {
-        self.use_random_ks(k_number);
-        let result = self.do_run(k_number);
+    /// # Arguments
+    /// * `a` - The number of colors to pick for our initial k clusters
+    /// * `config` - The `Config` for the run
+    fn use_kmeans_parallel(&mut self, a: usize, config: &Config) -> usize {
+        let weights = self.working_weights();
+        let picks = random::with_source(config.seed, self.random.as_mut(), |rng| {
+            init::kmeans_parallel(
+                &self.working_points,
+                &weights,
+                config.color_space,
+                config.distance_metric,
+                a,
+                rng,
+            )
+        });
 
-        self.results = vec![result];
+        self.initial_ks = picks
+            .into_iter()
+            .map(|i| self.working_colors[i].clone())
+            .collect();
+        self.initial_ks.len()
+    }
 
-        // JsValue::from_serde(&self.results[0]).unwrap()
-        self.results[0].clone()
+    /// The weight of each working color, in working color order
+    fn working_weights(&self) -> Vec<f64> {
+        self.working_colors
+            .iter()
+            .map(|c| self.working_colors_weights[c])
+            .collect()
     }
 
-    /// Performs multiple runs using `k` numbers between 1 and 20 and then uses
-    /// analysis to determine the most appropriate number of `k` clusters to use
-    /// for the provided image. Once determined the `RunResult` for this `k` number
-    /// is returned
-    pub fn with_derived_k_number(&mut self) -> RunResult {
-        self.results = vec![];
+    /// Perform a 'run' of the clustering arlorithm taking a specified
+    /// number of initial k colors from ImageKmeans.initial_ks
+    ///
+    /// # Arguments
+    /// * `num_ks` - How many k clusters to run the algorithm for, any `locked_colors` come first
+    ///   and the rest will be taken [0..] from the ImageKmeans.initial_ks
+    /// * `config` - The `Config` for this run
+    /// * `cancel` - Checked between iterations, the run stops with a
+    ///   `KmeansError::Cancelled` when this returns `true`
+    fn do_run(
+        &self,
+        num_ks: usize,
+        config: &Config,
+        cancel: &mut dyn FnMut() -> bool,
+    ) -> Result<RunResult, KmeansError> {
+        if cancel() {
+            return Err(KmeansError::Cancelled);
+        }
 
-        self.use_random_ks(10);
+        let locked = config.locked_colors.len();
+        let free = num_ks.saturating_sub(locked).min(self.initial_ks.len());
+        let mut initial = [&config.locked_colors[..], &self.initial_ks[..free]].concat();
+        if let Some(gamma) = config.apply_gamma {
+            for color in &mut initial[..locked] {
+                *color = color.with_gamma(gamma);
+            }
+        }
+
+        // the history is built from the clusters of each iteration's trace
+        let traced;
+        let run_config = if config.centroid_history && !config.trace {
+            traced = Config {
+                trace: true,
+                ..config.clone()
+            };
+            &traced
+        } else {
+            config
+        };
+        // a Hilbert run doesn't start from initial clusters
+        let history_start = (config.centroid_history && config.algorithm != Algorithm::Hilbert1D)
+            .then(|| initial.clone());
+
+        let start = now_ms();
+        let mut result = match config.algorithm {
+            Algorithm::KMeans => self.run_kmeans(initial, run_config, cancel)?,
+            Algorithm::KMedoids => self.run_kmedoids(initial, run_config),
+            Algorithm::Hilbert1D => self.run_hilbert(initial.len(), run_config),
+            Algorithm::FuzzyCMeans { m } => self.run_fuzzy(initial, m, run_config, cancel)?,
+        };
+        let iterations_ms = now_ms() - start;
 
-        for i in 1..=10 {
-            self.results.push(self.do_run(i));
+        if let Some(fraction) = config.min_cluster_fraction {
+            self.drop_small_clusters(&mut result, fraction, config);
         }
 
-        let wcss = self.results.iter().map(|r| r.wcss).collect::<Vec<f32>>();
+        let mut float_centroids = config.float_centroids.then(|| {
+            self.float_means(
+                &result.clusters,
+                locked,
+                config.color_space,
+                config.distance_metric,
+            )
+        });
 
-        let (x1, y1) = (1.0, wcss[0]);
-        let (x2, y2) = (11.0, wcss[wcss.len() - 1]);
+        if config.snap_only_final {
+            result.clusters = result
+                .clusters
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    if i < locked {
+                        c.clone()
+                    } else {
+                        self.nearest_color(c, config.color_space)
+                    }
+                })
+                .collect();
+        }
 
-        let mut distances: Vec<f32> = vec![];
+        if config.collision == CollisionMode::Reseed && self.reseed_collisions(&mut result.clusters)
+        {
+            result.wcss =
+                self.palette_wcss(&result.clusters, config.color_space, config.distance_metric);
+        }
 
-        for (i, sum) in wcss.iter().enumerate() {
-            let x0 = (i + 1) as f32;
-            let y0 = *sum;
-            let num = f32::abs((y2 - y1) * x0 - (x2 - x1) * y0 + x2 * y1 - y2 * x1);
-            let denum = f32::sqrt(f32::powi(y2 - y1, 2) + f32::powi(x2 - x1, 2));
-            distances.push(num / denum);
+        if let Some(min_sep) = config.min_separation {
+            result.clusters = self.separate_clusters(
+                &result.clusters,
+                locked,
+                min_sep,
+                config.color_space,
+                config.distance_metric,
+            );
+            result.wcss =
+                self.palette_wcss(&result.clusters, config.color_space, config.distance_metric);
         }
 
-        let max_dist = distances.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
-        let max_index = distances.iter().position(|&r| r == max_dist).unwrap();
+        if config.maximize_spread {
+            result.clusters = self.spread_clusters(
+                &result.clusters,
+                locked,
+                config.color_space,
+                config.distance_metric,
+            );
+            result.wcss =
+                self.palette_wcss(&result.clusters, config.color_space, config.distance_metric);
+        }
+
+        if config.guarantee_k {
+            let (space, metric) = (config.color_space, config.distance_metric);
+            let collided = self.reseed_collisions(&mut result.clusters);
+            if self.reseed_empty_clusters(&mut result.clusters, locked, space, metric) || collided {
+                result.wcss = self.palette_wcss(&result.clusters, space, metric);
+            }
+        }
+
+        if config.centroid_history {
+            let mut history: Vec<Vec<Color>> = history_start.into_iter().collect();
+            history.extend(result.trace.iter().flatten().map(|t| t.clusters.clone()));
+            if history.last() != Some(&result.clusters) {
+                history.push(result.clusters.clone());
+            }
+            result.centroid_history = Some(history);
+            if !config.trace {
+                result.trace = None;
+            }
+        }
+
+        result.counts =
+            self.cluster_counts(&result.clusters, config.color_space, config.distance_metric);
+        result.variance_explained =
+            self.variance_explained(&result.clusters, config.color_space, config.distance_metric);
+
+        if let Some(gamma) = config.apply_gamma {
+            let inverse = |clusters: &mut Vec<Color>| {
+                for color in clusters.iter_mut() {
+                    *color = color.with_gamma(1.0 / gamma);
+                }
+                // locked colors come back exactly as given, not rounded twice
+                clusters[..locked].clone_from_slice(&config.locked_colors);
+            };
+            inverse(&mut result.clusters);
+            for trace in result.trace.iter_mut().flatten() {
+                inverse(&mut trace.clusters);
+            }
+            for clusters in result.centroid_history.iter_mut().flatten() {
+                inverse(clusters);
+            }
+            for (i, centroid) in float_centroids.iter_mut().flatten().enumerate() {
+                *centroid = if i < locked {
+                    let color = &config.locked_colors[i];
+                    [color.r as f32, color.g as f32, color.b as f32]
+                } else {
+                    centroid.map(|c| (c / 255.0).powf(1.0 / gamma) * 255.0)
+                };
+            }
+        }
+        result.distinct = count_distinct(&result.clusters);
 
-        // JsValue::from_serde::<RunResult>(&self.results[max_index]).unwrap()
-        self.results[max_index].clone()
+        if config.sort_order == SortOrder::FirstAppearance {
+            let order = self.first_appearances(
+                &result.clusters,
+                config.color_space,
+                config.distance_metric,
+            );
+            result.clusters = order.iter().map(|&i| result.clusters[i].clone()).collect();
+            result.counts = order.iter().map(|&i| result.counts[i]).collect();
+            result.variance_explained = order
+                .iter()
+                .map(|&i| result.variance_explained[i])
+                .collect();
+            float_centroids = float_centroids.map(|c| order.iter().map(|&i| c[i]).collect());
+        }
+        result.float_centroids = float_centroids;
+        if config.measure {
+            result.timing = Some(RunTiming {
+                iterations_ms,
+                ..self.timing
+            });
+        }
+        Ok(result)
     }
-}
 
-impl ImageKmeans {
-    /// Take a random number of colors from the complete list of the given image
-    /// and set these as `ImageKmeans.initial_ks`
+    /// The indices of the clusters ordered by the first pixel of the image
+    /// nearest to each, scanning from the top left. Clusters no pixel is
+    /// nearest to keep their order after the rest
     ///
     /// # Arguments
-    /// * `a` - The number of random colors to pick for our initial k clusters
-    fn use_random_ks(&mut self, a: usize) {
-        let rng = &mut rand::thread_rng();
-        self.initial_ks = self.colors.clone().into_iter().choose_multiple(rng, a);
+    /// * `clusters` - The clusters to order
+    /// * `space` - The color space the clusters were formed in
+    /// * `metric` - How distances are measured in `space`
+    fn first_appearances(
+        &self,
+        clusters: &[Color],
+        space: ColorSpace,
+        metric: DistanceMetric,
+    ) -> Vec<usize> {
+        let assign = nearest_cluster_in(clusters, space, metric);
+        let mut first = vec![usize::MAX; clusters.len()];
+        let mut nearest: BTreeMap<&Color, usize> = BTreeMap::new();
+        let mut unseen = clusters.len();
+
+        for (pixel, color) in self.colors.iter().enumerate() {
+            if unseen == 0 {
+                break;
+            }
+            let k = *nearest.entry(color).or_insert_with(|| assign(color).0);
+            if first[k] == usize::MAX {
+                first[k] = pixel;
+                unseen -= 1;
+            }
+        }
+
+        let mut order: Vec<usize> = (0..clusters.len()).collect();
+        order.sort_by_key(|&k| first[k]);
+        order
     }
 
-    /// Perform a 'run' of the k-means clustering arlorithm taking a specified
-    /// number of initial k colors from ImageKmeans.initial_ks
+    /// Iterate k-means from the given initial clusters
     ///
     /// # Arguments
-    /// * `num_ks` - How many k clusters to run the algorithm for, these will be taken [0..num_ks]
-    ///   from the ImageKmeans.initial_ks
-    fn do_run(&self, num_ks: usize) -> RunResult {
+    /// * `initial` - The initial clusters
+    /// * `config` - The `Config` for this run
+    /// * `cancel` - Checked between iterations, the run stops with a
+    ///   `KmeansError::Cancelled` when this returns `true`
+    fn run_kmeans(
+        &self,
+        initial: Vec<Color>,
+        config: &Config,
+        cancel: &mut dyn FnMut() -> bool,
+    ) -> Result<RunResult, KmeansError> {
         let mut iterations = 0;
         #[allow(unused_assignments)]
         let mut square_distance_sum = 0.0;
         let mut distance_shift = 0.0;
 
-        let mut clusters = self.initial_ks[..num_ks].to_vec();
+        let mut clusters = initial;
+        let mut assignments = vec![];
+        let mut trace = config.trace.then(Vec::new);
+        let mut convergence = vec![];
+        let (min_iterations, max_iterations) = config.iteration_limits();
 
         loop {
-            let (new_clusters, distance_sum) = self.calc_new_clusters(&clusters);
+            let (new_clusters, distance_sum, reassigned) =
+                self.calc_new_clusters(&clusters, config, &mut assignments);
 
             for i in 0..new_clusters.len() {
-                distance_shift += self.calc_euclidean_dist(&new_clusters[i], &clusters[i])
+                distance_shift += new_clusters[i].distance_to(&clusters[i], ColorSpace::Rgb)
             }
 
             distance_shift /= new_clusters.len() as f32;
             clusters = new_clusters;
             square_distance_sum = distance_sum;
+            convergence.push(square_distance_sum);
+
+            if let Some(trace) = trace.as_mut() {
+                trace.push(IterationTrace {
+                    iteration: iterations,
+                    clusters: clusters.clone(),
+                    wcss: square_distance_sum,
+                    shift: distance_shift,
+                    reassigned,
+                });
+            }
 
-            if distance_shift < 0_f32 || iterations == 10 {
+            // with no color changing cluster the means can't move again
+            let converged = reassigned == 0;
+            if (converged && iterations + 1 >= min_iterations) || iterations + 1 >= max_iterations {
                 break;
             }
 
+            if cancel() {
+                return Err(KmeansError::Cancelled);
+            }
+
             iterations += 1;
             distance_shift = 0.0;
         }
 
-        RunResult {
-            ks: num_ks,
+        Ok(RunResult {
+            ks: clusters.len(),
+            distinct: count_distinct(&clusters),
+            counts: vec![],
+            variance_explained: vec![],
+            convergence,
+            float_centroids: None,
+            iterations: iterations + 1,
             clusters,
             wcss: square_distance_sum,
+            trace,
+            centroid_history: None,
+            timing: None,
+            warnings: vec![],
+        })
+    }
+
+    /// Run k-medoids from the given initial clusters, making PAM swaps until
+    /// none improve the sum of distances or the iteration limit is reached
+    ///
+    /// # Arguments
+    /// * `initial` - The initial clusters, these must be working colors
+    /// * `config` - The `Config` for this run
+    fn run_kmedoids(&self, initial: Vec<Color>, config: &Config) -> RunResult {
+        let weights = self.working_weights();
+        let medoids = initial
+            .iter()
+            .filter_map(|c| self.working_colors.iter().position(|w| w == c))
+            .collect();
+        let mut state =
+            kmedoids::Medoids::new(&self.working_points, &weights, config.color_space, medoids);
+        let mut trace = config.trace.then(Vec::new);
+        let to_colors = |medoids: &[usize]| -> Vec<Color> {
+            medoids
+                .iter()
+                .map(|&m| self.working_colors[m].clone())
+                .collect()
+        };
+
+        let (min_iterations, max_iterations) = config.iteration_limits();
+        let mut iterations = 0;
+        for iteration in 0..max_iterations {
+            iterations += 1;
+            let previous = to_colors(&state.medoids);
+            let previous_assignments = state.assignments().to_vec();
+            let swapped = state.swap();
+
+            if let Some(trace) = trace.as_mut() {
+                let clusters = to_colors(&state.medoids);
+                let shift = clusters
+                    .iter()
+                    .zip(&previous)
+                    .map(|(a, b)| a.distance_to(b, ColorSpace::Rgb))
+                    .sum::<f32>()
+                    / clusters.len() as f32;
+                let reassigned = state
+                    .assignments()
+                    .iter()
+                    .zip(&previous_assignments)
+                    .filter(|(a, b)| a != b)
+                    .count();
+                trace.push(IterationTrace {
+                    iteration,
+                    clusters,
+                    wcss: state.cost(),
+                    shift,
+                    reassigned,
+                });
+            }
+
+            if !swapped && iterations >= min_iterations {
+                break;
+            }
+        }
+
+        let clusters = to_colors(&state.medoids);
+        RunResult {
+            ks: clusters.len(),
+            distinct: count_distinct(&clusters),
+            counts: vec![],
+            variance_explained: vec![],
+            convergence: vec![],
+            float_centroids: None,
+            iterations,
+            clusters,
+            wcss: state.cost(),
+            trace,
+            centroid_history: None,
+            timing: None,
+            warnings: vec![],
         }
     }
 
-    fn calc_new_clusters(&self, k_clusters: &Vec<Color>) -> (Vec<Color>, f32) {
-        let mut new_clusters = vec![vec![]; k_clusters.len()];
+    /// Iterate fuzzy c-means from the given initial clusters until the
+    /// clusters stop changing or the iteration limit is reached
+    ///
+    /// # Arguments
+    /// * `initial` - The initial clusters
+    /// * `m` - The fuzziness exponent
+    /// * `config` - The `Config` for this run
+    /// * `cancel` - Checked between iterations, the run stops with a
+    ///   `KmeansError::Cancelled` when this returns `true`
+    fn run_fuzzy(
+        &self,
+        initial: Vec<Color>,
+        m: f32,
+        config: &Config,
+        cancel: &mut dyn FnMut() -> bool,
+    ) -> Result<RunResult, KmeansError> {
+        let space = config.color_space;
+        let weights = self.working_weights();
+        let fuzzy = fuzzy::FuzzyCMeans::new(&self.working_points, &weights, space, m);
+        let nearest = |centres: &[Point]| -> Vec<usize> {
+            self.working_points
+                .iter()
+                .map(|p| space.nearest(p, centres).0)
+                .collect()
+        };
 
-        for color in &self.colors {
-            let distances = k_clusters
+        let mut centres: Vec<Point> = initial.iter().map(|c| space.to_point(c)).collect();
+        let mut clusters = initial;
+        let mut trace = config.trace.then(Vec::new);
+        let mut assignments = if config.trace {
+            nearest(&centres)
+        } else {
+            vec![]
+        };
+
+        let (min_iterations, max_iterations) = config.iteration_limits();
+        let mut iterations = 0;
+        for iteration in 0..max_iterations {
+            iterations += 1;
+            centres = fuzzy.update(&centres);
+            let new_clusters: Vec<Color> = centres.iter().map(|p| space.to_color(*p)).collect();
+            let shift = new_clusters
                 .iter()
-                .map(|k| self.calc_euclidean_dist(k, color))
-                .collect::<Vec<f32>>();
+                .zip(&clusters)
+                .map(|(a, b)| a.distance_to(b, ColorSpace::Rgb))
+                .sum::<f32>()
+                / new_clusters.len() as f32;
+            let changed = new_clusters != clusters;
+            clusters = new_clusters;
+
+            if let Some(trace) = trace.as_mut() {
+                let new_assignments = nearest(&centres);
+                trace.push(IterationTrace {
+                    iteration,
+                    clusters: clusters.clone(),
+                    wcss: fuzzy.objective(&centres),
+                    shift,
+                    reassigned: new_assignments
+                        .iter()
+                        .zip(&assignments)
+                        .filter(|(a, b)| a != b)
+                        .count(),
+                });
+                assignments = new_assignments;
+            }
+
+            // the memberships only depend on the centres, so once they settle
+            // on the same colors further updates barely move them
+            if !changed && iterations >= min_iterations {
+                break;
+            }
 
-            let min_distance = distances.iter().fold(f32::INFINITY, |a, &b| a.min(b));
-            let selected_k = distances.iter().position(|&r| r == min_distance).unwrap();
-            new_clusters[selected_k].push(color);
+            if cancel() {
+                return Err(KmeansError::Cancelled);
+            }
         }
 
-        let colors: Vec<Color> = new_clusters
+        Ok(RunResult {
+            ks: clusters.len(),
+            distinct: count_distinct(&clusters),
+            counts: vec![],
+            variance_explained: vec![],
+            convergence: vec![],
+            float_centroids: None,
+            iterations,
+            wcss: fuzzy.objective(&centres),
+            clusters,
+            trace,
+            centroid_history: None,
+            timing: None,
+            warnings: vec![],
+        })
+    }
+
+    /// Remove the clusters nearest to less than `fraction` of the pixels from a
+    /// result, keeping locked colors and the most populous cluster. With
+    /// k-means the remaining clusters take one more assignment pass so they
+    /// absorb the dropped clusters' colors
+    ///
+    /// # Arguments
+    /// * `result` - The converged result
+    /// * `fraction` - The smallest fraction of pixels a cluster may have
+    /// * `config` - The `Config` for this run
+    fn drop_small_clusters(&self, result: &mut RunResult, fraction: f32, config: &Config) {
+        let counts =
+            self.cluster_counts(&result.clusters, config.color_space, config.distance_metric);
+        let total: usize = counts.iter().sum();
+        let largest = (0..counts.len()).rev().max_by_key(|&i| counts[i]);
+        let locked = config.locked_colors.len();
+
+        let kept: Vec<Color> = result
+            .clusters
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| {
+                i < locked || Some(i) == largest || counts[i] as f32 >= fraction * total as f32
+            })
+            .map(|(_, c)| c.clone())
+            .collect();
+        if kept.len() == result.clusters.len() {
+            return;
+        }
+
+        if config.algorithm == Algorithm::KMeans {
+            let (clusters, wcss, _) = self.calc_new_clusters(&kept, config, &mut vec![]);
+            result.clusters = clusters;
+            result.wcss = wcss;
+        } else {
+            result.wcss = self.palette_wcss(&kept, config.color_space, config.distance_metric);
+            result.clusters = kept;
+        }
+        result.ks = result.clusters.len();
+    }
+
+    /// Count the working colors nearest to each cluster in the given color
+    /// space, weighted by their counts
+    ///
+    /// # Arguments
+    /// * `clusters` - The clusters to count for
+    /// * `space` - The color space to assign in, this must be the space
+    ///   `ImageKmeans.working_points` were built for
+    /// * `metric` - How distances are measured in `space`
+    fn cluster_counts(
+        &self,
+        clusters: &[Color],
+        space: ColorSpace,
+        metric: DistanceMetric,
+    ) -> Vec<usize> {
+        let mut counts = vec![0; clusters.len()];
+        if clusters.is_empty() {
+            return counts;
+        }
+        let k_points: Vec<Point> = clusters.iter().map(|k| space.to_point(k)).collect();
+
+        for (point, color) in self.working_points.iter().zip(&self.working_colors) {
+            let (nearest, _) = metric.nearest(space, point, &k_points);
+            counts[nearest] += self.working_colors_counts[color];
+        }
+
+        counts
+    }
+
+    /// Move every cluster repeating an earlier cluster's color to the unused
+    /// working color furthest from all of the clusters, one at a time so each
+    /// move accounts for the last. Earlier clusters are never moved so locked
+    /// colors stay put. Returns whether any cluster was moved
+    ///
+    /// # Arguments
+    /// * `clusters` - The clusters to fix up
+    fn reseed_collisions(&self, clusters: &mut [Color]) -> bool {
+        let mut moved = false;
+
+        for i in 1..clusters.len() {
+            if !clusters[..i].contains(&clusters[i]) {
+                continue;
+            }
+
+            if let Some(color) = self.furthest_unused(clusters) {
+                clusters[i] = color;
+                moved = true;
+            }
+        }
+
+        moved
+    }
+
+    /// Move every cluster no working color is nearest to, past the locked
+    /// ones, to the unused working color furthest from all of the clusters.
+    /// Moving one cluster can empty another so this repeats until none are
+    /// empty or there are no unused working colors left. Returns whether any
+    /// cluster was moved
+    ///
+    /// # Arguments
+    /// * `clusters` - The clusters to fix up
+    /// * `locked` - The number of leading clusters that must not move
+    /// * `space` - The color space to assign in
+    /// * `metric` - How distances are measured in `space`
+    fn reseed_empty_clusters(
+        &self,
+        clusters: &mut [Color],
+        locked: usize,
+        space: ColorSpace,
+        metric: DistanceMetric,
+    ) -> bool {
+        let mut moved = false;
+
+        for _ in 0..self.working_colors.len() {
+            let counts = self.cluster_counts(clusters, space, metric);
+            let Some(empty) = (locked..clusters.len()).find(|&i| counts[i] == 0) else {
+                break;
+            };
+            let Some(color) = self.furthest_unused(clusters) else {
+                break;
+            };
+            clusters[empty] = color;
+            moved = true;
+        }
+
+        moved
+    }
+
+    /// The working color not already a cluster that is furthest from all of
+    /// the clusters, if there is one
+    ///
+    /// # Arguments
+    /// * `clusters` - The current clusters
+    fn furthest_unused(&self, clusters: &[Color]) -> Option<Color> {
+        self.working_colors
+            .iter()
+            .filter(|c| !clusters.contains(c))
+            .max_by_key(|c| clusters.iter().map(|k| c.squared_dist(k)).min())
+            .cloned()
+    }
+
+    /// Cluster the working colors in one pass by splitting them, sorted along
+    /// a Hilbert curve, into `k` runs at the largest jumps along the curve.
+    /// Each cluster is the mean of its run
+    ///
+    /// # Arguments
+    /// * `k` - The number of clusters, at most the number of working colors
+    /// * `config` - The `Config` for this run
+    fn run_hilbert(&self, k: usize, config: &Config) -> RunResult {
+        let mut order: Vec<(u32, usize)> = self
+            .working_colors
             .iter()
-            .map(|c_list| {
-                let mut r = 0;
-                let mut b = 0;
-                let mut g = 0;
-
-                c_list.iter().for_each(|color| {
-                    r += color.r;
-                    b += color.b;
-                    g += color.g;
+            .enumerate()
+            .map(|(i, c)| (hilbert::hilbert_index(c), i))
+            .collect();
+        order.sort();
+
+        let indices: Vec<u32> = order.iter().map(|(index, _)| *index).collect();
+        let mut starts = hilbert::segment_starts(&indices, k);
+        starts.push(order.len());
+
+        let clusters: Vec<Color> = starts
+            .windows(2)
+            .map(|run| {
+                let members: Vec<usize> = order[run[0]..run[1]].iter().map(|(_, i)| *i).collect();
+                self.mean_color(&members, config.color_space)
+            })
+            .collect();
+        let wcss = self.palette_wcss(&clusters, config.color_space, config.distance_metric);
+        let trace = config.trace.then(|| {
+            vec![IterationTrace {
+                iteration: 0,
+                clusters: clusters.clone(),
+                wcss,
+                shift: 0.0,
+                reassigned: self.working_colors.len(),
+            }]
+        });
+
+        RunResult {
+            ks: clusters.len(),
+            distinct: count_distinct(&clusters),
+            counts: vec![],
+            variance_explained: vec![],
+            convergence: vec![],
+            float_centroids: None,
+            iterations: 1,
+            clusters,
+            wcss,
+            trace,
+            centroid_history: None,
+            timing: None,
+            warnings: vec![],
+        }
+    }
+
+    /// Swap the clusters for distinct real colors at least `min_sep` apart.
+    /// Clusters are taken in order, each picking the nearest unused working
+    /// color that is far enough from those already picked. If no such color
+    /// remains the unused color furthest from those picked is used instead
+    ///
+    /// # Arguments
+    /// * `clusters` - The converged clusters
+    /// * `locked` - How many of the leading clusters are locked, these are
+    ///   kept as they are
+    /// * `min_sep` - The minimum distance wanted between any two clusters
+    /// * `space` - The color space of `ImageKmeans.working_points`
+    /// * `metric` - How distances are measured in `space`
+    fn separate_clusters(
+        &self,
+        clusters: &[Color],
+        locked: usize,
+        min_sep: f32,
+        space: ColorSpace,
+        metric: DistanceMetric,
+    ) -> Vec<Color> {
+        let distance = |p: &Point, q: &Point| metric.distance(space, p, q);
+        let mut picked: Vec<Color> = clusters[..locked].to_vec();
+        let mut picked_points: Vec<Point> = picked.iter().map(|c| space.to_point(c)).collect();
+
+        for cluster in &clusters[locked..] {
+            let target = space.to_point(cluster);
+            let unused = self
+                .working_colors
+                .iter()
+                .zip(&self.working_points)
+                .filter(|(c, _)| !picked.contains(c));
+            let spacing = |p: &Point| {
+                picked_points
+                    .iter()
+                    .map(|q| distance(q, p))
+                    .fold(f32::INFINITY, f32::min)
+            };
+
+            let next = unused
+                .clone()
+                .filter(|(_, p)| spacing(p) >= min_sep)
+                .min_by(|(_, a), (_, b)| distance(&target, a).total_cmp(&distance(&target, b)))
+                .or_else(|| unused.max_by(|(_, a), (_, b)| spacing(a).total_cmp(&spacing(b))));
+
+            match next {
+                Some((color, point)) => {
+                    picked.push(color.clone());
+                    picked_points.push(*point);
+                }
+                None => {
+                    picked.push(cluster.clone());
+                    picked_points.push(target);
+                }
+            }
+        }
+
+        picked
+    }
+
+    /// Swap the clusters for real colors spread as far apart as possible by
+    /// farthest-point selection. The locked clusters are kept, or the most
+    /// frequent working color starts the selection without any, then each
+    /// next color is the unused working color furthest from all of those
+    /// picked. If the working colors run out the remaining clusters are kept
+    /// as they are
+    ///
+    /// # Arguments
+    /// * `clusters` - The converged clusters
+    /// * `locked` - How many of the leading clusters are locked, these are
+    ///   kept as they are
+    /// * `space` - The color space of `ImageKmeans.working_points`
+    /// * `metric` - How distances are measured in `space`
+    fn spread_clusters(
+        &self,
+        clusters: &[Color],
+        locked: usize,
+        space: ColorSpace,
+        metric: DistanceMetric,
+    ) -> Vec<Color> {
+        let mut picked: Vec<Color> = clusters[..locked].to_vec();
+        let mut picked_points: Vec<Point> = picked.iter().map(|c| space.to_point(c)).collect();
+
+        for cluster in &clusters[locked..] {
+            let spacing = |p: &Point| {
+                picked_points
+                    .iter()
+                    .map(|q| metric.distance(space, q, p))
+                    .fold(f32::INFINITY, f32::min)
+            };
+            // the first furthest color wins ties, so the most frequent
+            let next = self
+                .working_colors
+                .iter()
+                .zip(&self.working_points)
+                .filter(|(c, _)| !picked.contains(c))
+                .min_by(|(_, a), (_, b)| spacing(b).total_cmp(&spacing(a)));
+
+            match next {
+                Some((color, point)) => {
+                    picked.push(color.clone());
+                    picked_points.push(*point);
+                }
+                None => {
+                    picked_points.push(space.to_point(cluster));
+                    picked.push(cluster.clone());
+                }
+            }
+        }
+
+        picked
+    }
+
+    /// The mean of some working colors in the given color space, weighted by
+    /// their weights
+    ///
+    /// # Arguments
+    /// * `members` - Indices of the working colors, must not be empty
+    /// * `space` - The color space to average in, this must be the space
+    ///   `ImageKmeans.working_points` were built for
+    fn mean_color(&self, members: &[usize], space: ColorSpace) -> Color {
+        match space {
+            ColorSpace::Rgb => {
+                let mut r = 0.0;
+                let mut b = 0.0;
+                let mut g = 0.0;
+                let mut total = 0.0;
+
+                members.iter().for_each(|&i| {
+                    let color = &self.working_colors[i];
+                    let weight = self.working_colors_weights[color];
+                    r += color.r as f64 * weight;
+                    b += color.b as f64 * weight;
+                    g += color.g as f64 * weight;
+                    total += weight;
                 });
 
                 Color {
-                    r: (r / c_list.len() as i32),
-                    g: (g / c_list.len() as i32),
-                    b: (b / c_list.len() as i32),
+                    r: (r / total).floor() as i32,
+                    g: (g / total).floor() as i32,
+                    b: (b / total).floor() as i32,
+                }
+            }
+            _ => space.to_color(space.weighted_mean(members.iter().map(|&i| {
+                let weight = self.working_colors_weights[&self.working_colors[i]];
+                (&self.working_points[i], weight)
+            }))),
+        }
+    }
+
+    /// The mean of some working colors as `mean_color` gives, but recomputed
+    /// without the colors furthest from that first mean making up `fraction`
+    /// of the members' total weight. At least the nearest color is always kept
+    ///
+    /// # Arguments
+    /// * `members` - Indices of the working colors, must not be empty
+    /// * `space` - The color space to average in, this must be the space
+    ///   `ImageKmeans.working_points` were built for
+    /// * `fraction` - The fraction of the count to trim, from `0.0` to below `1.0`
+    fn trimmed_mean_color(&self, members: &[usize], space: ColorSpace, fraction: f32) -> Color {
+        let mean = space.to_point(&self.mean_color(members, space));
+        let mut by_distance = members.to_vec();
+        by_distance.sort_by(|&a, &b| {
+            space
+                .distance(&self.working_points[a], &mean)
+                .total_cmp(&space.distance(&self.working_points[b], &mean))
+        });
+
+        let weight = |i: usize| self.working_colors_weights[&self.working_colors[i]];
+        let total: f64 = members.iter().map(|&i| weight(i)).sum();
+        let mut trim = fraction as f64 * total;
+        while by_distance.len() > 1 && weight(*by_distance.last().unwrap()) <= trim {
+            trim -= weight(by_distance.pop().unwrap());
+        }
+
+        self.mean_color(&by_distance, space)
+    }
+
+    /// The weighted geometric median of some working colors in the given
+    /// color space
+    ///
+    /// # Arguments
+    /// * `members` - Indices of the working colors, must not be empty
+    /// * `space` - The color space of `ImageKmeans.working_points`
+    fn median_color(&self, members: &[usize], space: ColorSpace) -> Color {
+        let points: Vec<(&Point, f64)> = members
+            .iter()
+            .map(|&i| {
+                let weight = self.working_colors_weights[&self.working_colors[i]];
+                (&self.working_points[i], weight)
+            })
+            .collect();
+
+        space.to_color(space.geometric_median(&points))
+    }
+
+    /// Assign each working color, jittered when `jitter` is configured, to its
+    /// nearest cluster in the given color space and calculate the new clusters
+    /// as the weighted mean of their members, returning them along with their
+    /// WCSS and how many working colors changed cluster
+    ///
+    /// # Arguments
+    /// * `k_clusters` - The current clusters, any `locked_colors` first. These
+    ///   take members as usual but are never moved
+    /// * `config` - The `Config` for this run, its color space must be the one
+    ///   `ImageKmeans.working_points` were built for
+    /// * `assignments` - The cluster each working color was assigned to in the
+    ///   previous iteration, empty before the first. This is updated with the
+    ///   new assignments
+    fn calc_new_clusters(
+        &self,
+        k_clusters: &[Color],
+        config: &Config,
+        assignments: &mut Vec<usize>,
+    ) -> (Vec<Color>, f32, usize) {
+        let (locked, space) = (config.locked_colors.len(), config.color_space);
+        let metric = config.distance_metric;
+        let mut new_clusters = vec![vec![]; k_clusters.len()];
+        let k_points: Vec<Point> = k_clusters.iter().map(|k| space.to_point(k)).collect();
+        let mut reassigned = 0;
+        assignments.resize(self.working_points.len(), usize::MAX);
+
+        for (i, point) in self.working_points.iter().enumerate() {
+            let (selected_k, _) = match self.jittered_points.get(i) {
+                Some(jittered) => metric.nearest_by_distance(space, jittered, &k_points),
+                None => metric.nearest(space, point, &k_points),
+            };
+            new_clusters[selected_k].push(i);
+            if assignments[i] != selected_k {
+                assignments[i] = selected_k;
+                reassigned += 1;
+            }
+        }
+
+        let colors: Vec<Color> = new_clusters
+            .iter()
+            .zip(k_clusters)
+            .enumerate()
+            .map(|(i, (members, previous))| match space {
+                // an empty cluster has no mean, it stays where it was
+                _ if members.is_empty() || i < locked => previous.clone(),
+                _ if config.centroid_mode == CentroidMode::GeometricMedian => {
+                    self.median_color(members, space)
                 }
+                _ => match config.trimmed_mean_fraction {
+                    Some(fraction) => self.trimmed_mean_color(members, space, fraction),
+                    None => self.mean_color(members, space),
+                },
             })
             .collect();
 
+        // measured in the clustering space so the elbow analysis judges the
+        // same objective the iterations minimize
         let distance_sum = colors
             .iter()
             .zip(new_clusters)
-            .map(|(a, b)| {
+            .map(|(a, members)| {
+                let centre = space.to_point(a);
                 let mut sum_total = 0.0;
-                for c in b {
-                    sum_total += self.calc_euclidean_dist(a, c).powi(2);
+                for i in members {
+                    let weight = self.working_colors_weights[&self.working_colors[i]];
+                    sum_total +=
+                        metric.squared_distance(space, &centre, &self.working_points[i]) * weight;
                 }
-                sum_total
+                sum_total as f32
             })
             .sum();
 
-        (colors, distance_sum)
+        (colors, distance_sum, reassigned)
+    }
+
+    /// Find the cluster closest to a color, returning its index and the
+    /// distance to it. Ties go to the earliest cluster
+    ///
+    /// # Arguments
+    /// * `color` - the color to assign
+    /// * `k_clusters` - the clusters to pick from, must not be empty
+    fn nearest_cluster(&self, color: &Color, k_clusters: &[Color]) -> (usize, f32) {
+        k_clusters
+            .iter()
+            .map(|k| color.squared_dist(k))
+            .enumerate()
+            .min_by_key(|&(_, d2)| d2)
+            .map_or((0, f32::INFINITY), |(i, d2)| (i, (d2 as f32).sqrt()))
     }
 
-    /// Calculate the euclidean distance between two Color points in 3D space
+    /// Find the color present in the image closest to the given color in the
+    /// color space the working colors were built for
     ///
     /// # Arguments
-    /// * `p` - first color
-    /// * `q` - second color
-    fn calc_euclidean_dist(&self, p: &Color, q: &Color) -> f32 {
-        f32::sqrt((i32::pow(p.r - q.r, 2) + i32::pow(p.g - q.g, 2) + i32::pow(p.b - q.b, 2)) as f32)
+    /// * `target` - the color to match against the image colors
+    /// * `space` - the color space of `ImageKmeans.working_points`
+    fn nearest_color(&self, target: &Color, space: ColorSpace) -> Color {
+        if self.working_points.is_empty() {
+            return target.clone();
+        }
+        let (i, _) = space.nearest(&space.to_point(target), &self.working_points);
+        self.working_colors[i].clone()
     }
 }
@@ -0,0 +1,88 @@
+/// The most data a stored deflate block can hold
+const MAX_STORED_BLOCK: usize = 65535;
+
+/// Encode 8-bit RGB pixel data as a PNG. The image data is deflated with
+/// stored blocks only, so nothing is compressed but no dependencies are
+/// needed, which is fine for small flat images such as palette swatches
+///
+/// # Arguments
+/// * `rgb` - The pixel data, 3 bytes per pixel in RGB order, row by row
+/// * `width` - The width of the image, must not be 0
+/// * `height` - The height of the image, must not be 0
+pub(crate) fn encode_rgb(rgb: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    let mut header = vec![];
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 8 bits per channel, truecolor, default compression, filter and no interlace
+    header.extend([8, 2, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+
+    // every scanline starts with its filter type, 0 for none
+    let row = width as usize * 3;
+    let scanlines: Vec<u8> = rgb
+        .chunks_exact(row)
+        .flat_map(|line| std::iter::once(0).chain(line.iter().copied()))
+        .collect();
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+/// Append a chunk with its length and CRC
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// Wrap some data in a zlib stream of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+/// The CRC-32 PNG chunks are checked with
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// The Adler-32 checksum ending a zlib stream
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
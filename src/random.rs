@@ -0,0 +1,83 @@
+use crate::color_space::Point;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// A source of randomness used when picking the initial k clusters. Any
+/// `rand` generator can be used directly, tests can implement this to inject
+/// a fixed sequence of picks
+pub trait RandomSource {
+    /// Return an index in the range `0..len`, `len` is always at least 1
+    fn next_index(&mut self, len: usize) -> usize;
+
+    /// Return a value in the range `0.0..1.0`, by default this is built from
+    /// `next_index`
+    fn next_unit(&mut self) -> f64 {
+        const STEPS: usize = 1 << 24;
+        self.next_index(STEPS) as f64 / STEPS as f64
+    }
+}
+
+impl<R: Rng> RandomSource for R {
+    fn next_index(&mut self, len: usize) -> usize {
+        self.gen_range(0..len)
+    }
+
+    fn next_unit(&mut self) -> f64 {
+        self.gen()
+    }
+}
+
+/// Run `f` with a generator seeded from `seed` when one is given, otherwise
+/// with `fallback`
+///
+/// # Arguments
+/// * `seed` - The optional seed
+/// * `fallback` - The source to use when there is no seed
+/// * `f` - The function needing a source of randomness
+pub(crate) fn with_source<T>(
+    seed: Option<u64>,
+    fallback: &mut dyn RandomSource,
+    f: impl FnOnce(&mut dyn RandomSource) -> T,
+) -> T {
+    match seed {
+        Some(seed) => f(&mut StdRng::seed_from_u64(seed)),
+        None => f(fallback),
+    }
+}
+
+/// Pick up to `a` distinct items from `items` using a partial Fisher-Yates
+/// shuffle driven by `rng`
+///
+/// # Arguments
+/// * `items` - The items to pick from
+/// * `a` - How many items to pick
+/// * `rng` - The source of randomness for the picks
+pub(crate) fn pick_distinct<T: Clone>(items: &[T], a: usize, rng: &mut dyn RandomSource) -> Vec<T> {
+    let mut pool = items.to_vec();
+    let picks = a.min(pool.len());
+
+    for i in 0..picks {
+        let j = i + rng.next_index(pool.len() - i);
+        pool.swap(i, j);
+    }
+
+    pool.truncate(picks);
+    pool
+}
+
+/// Add Gaussian noise to every coordinate of some points, the same noise for
+/// the same seed
+///
+/// # Arguments
+/// * `points` - The points to jitter
+/// * `sigma` - The standard deviation of the noise
+/// * `seed` - The seed for the noise
+pub(crate) fn gaussian_jitter(points: &[Point], sigma: f32, seed: u64) -> Vec<Point> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    // Box-Muller, 1 - u keeps the logarithm finite
+    let mut noise = || {
+        let (u, v): (f32, f32) = (rng.gen(), rng.gen());
+        (-2.0 * (1.0 - u).ln()).sqrt() * (std::f32::consts::TAU * v).cos() * sigma
+    };
+
+    points.iter().map(|p| p.map(|c| c + noise())).collect()
+}
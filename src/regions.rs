@@ -0,0 +1,66 @@
+/// Disjoint sets over indices, merging by size with path halving
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        UnionFind {
+            parent: (0..n).collect(),
+            size: vec![1; n],
+        }
+    }
+
+    /// The representative of the set `i` is in
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    /// Merge the sets `a` and `b` are in, the larger set's representative
+    /// represents both
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+/// Find the 4-connected regions of equal values in a grid of cells laid out
+/// row by row. Cells without a value belong to no region. Returns the index of
+/// a cell in each region along with the number of cells in it
+///
+/// # Arguments
+/// * `cells` - The value of each cell, row by row
+/// * `width` - The number of cells in a row, must not be 0
+pub(crate) fn regions<T: PartialEq>(cells: &[Option<T>], width: usize) -> Vec<(usize, usize)> {
+    let mut sets = UnionFind::new(cells.len());
+
+    for (i, cell) in cells.iter().enumerate() {
+        let Some(value) = cell else {
+            continue;
+        };
+        let same = |n: usize| cells[n].as_ref() == Some(value);
+        if i % width + 1 < width && same(i + 1) {
+            sets.union(i, i + 1);
+        }
+        if i + width < cells.len() && same(i + width) {
+            sets.union(i, i + width);
+        }
+    }
+
+    (0..cells.len())
+        .filter(|&i| cells[i].is_some() && sets.parent[i] == i)
+        .map(|i| (i, sets.size[i]))
+        .collect()
+}
@@ -8,3 +8,34 @@ pub fn set_panic_hook() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 }
+
+/// Milliseconds from an arbitrary starting point, only meaningful for
+/// measuring how long something took. In the browser this is
+/// `performance.now()` where available, in workers too
+#[cfg(all(target_arch = "wasm32", feature = "web"))]
+pub fn now_ms() -> f64 {
+    use wasm_bindgen::JsCast;
+
+    js_sys::Reflect::get(&js_sys::global(), &"performance".into())
+        .ok()
+        .and_then(|performance| performance.dyn_into::<web_sys::Performance>().ok())
+        .map_or_else(js_sys::Date::now, |performance| performance.now())
+}
+
+/// Milliseconds from an arbitrary starting point, only meaningful for
+/// measuring how long something took
+#[cfg(all(target_arch = "wasm32", not(feature = "web")))]
+pub fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+/// Milliseconds from an arbitrary starting point, only meaningful for
+/// measuring how long something took
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_ms() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
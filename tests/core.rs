@@ -0,0 +1,2766 @@
+//! Test suite for the clustering algorithm, these run natively with `cargo test`
+
+use image_kmeans::{
+    default_config, Algorithm, AlphaMode, CentroidMode, CollisionMode, Color, ColorSpace,
+    ColorTheme, Config, DistanceMetric, GrayscaleSplit, ImageKmeans, InitMethod, KSelection,
+    KmeansError, PaletteSummary, QuantizeStage, RandomSource, RegionWeighting, RunWarning,
+    SortOrder, CHANNEL_MAX,
+};
+use std::collections::BTreeMap;
+
+/// Build an opaque RGBA buffer from a list of RGB pixels
+fn rgba(pixels: &[(u8, u8, u8)]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|&(r, g, b)| [r, g, b, 255])
+        .collect()
+}
+
+#[test]
+fn fixed_k_larger_than_color_count_uses_available_colors() {
+    let data = rgba(&[
+        (250, 0, 0),
+        (255, 0, 0),
+        (252, 3, 1),
+        (0, 0, 250),
+        (1, 2, 255),
+        (3, 0, 252),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 2).unwrap();
+    let config = Config {
+        quantize_fact: Some(10),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(5, Some(config)).unwrap();
+
+    // the six near duplicates quantize to two colors
+    assert_eq!(kmeans.working_colors().len(), 2);
+    assert_eq!(result.ks, 2);
+    assert_eq!(result.clusters.len(), 2);
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    assert_eq!(clusters, vec![(0, 0, 250), (250, 0, 0)]);
+}
+
+#[test]
+fn empty_image_is_an_error() {
+    assert_eq!(
+        ImageKmeans::from_rgba(&[], 0, 0).err(),
+        Some(KmeansError::EmptyImage)
+    );
+}
+
+#[test]
+fn partial_pixel_data_is_an_error() {
+    assert_eq!(
+        ImageKmeans::from_rgba(&[255, 0, 0], 1, 1).err(),
+        Some(KmeansError::InvalidDimensions)
+    );
+}
+
+#[test]
+fn zero_k_is_an_error() {
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&[(255, 0, 0)]), 1, 1).unwrap();
+
+    assert!(matches!(
+        kmeans.with_fixed_k_number(0, None),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn coherence_weight_favours_contiguous_colors() {
+    let data = rgba(&[(255, 0, 0), (255, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 1).unwrap();
+
+    let plain = kmeans.with_fixed_k_number(1, None).unwrap();
+    let coherent = kmeans
+        .with_fixed_k_number(
+            1,
+            Some(Config {
+                coherence_weight: 1,
+                ..Config::default()
+            }),
+        )
+        .unwrap();
+
+    assert_eq!(plain.clusters[0].r, 170);
+    assert_eq!(coherent.clusters[0].r, 204);
+}
+
+#[test]
+fn fixed_k_batch_returns_a_result_per_k() {
+    let data = rgba(&[(255, 0, 0), (0, 255, 0), (0, 0, 255), (255, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+
+    let results = kmeans.fixed_k_batch(vec![2, 1, 3, 5], None).unwrap().0;
+
+    let ks: Vec<usize> = results.iter().map(|r| r.ks).collect();
+    assert_eq!(ks, vec![2, 1, 3, 3]);
+}
+
+/// Always picks the first remaining candidate
+struct FirstPick;
+
+impl RandomSource for FirstPick {
+    fn next_index(&mut self, _len: usize) -> usize {
+        0
+    }
+}
+
+#[test]
+fn injected_random_source_picks_initial_clusters() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    kmeans.set_random_source(Box::new(FirstPick));
+
+    let result = kmeans.with_fixed_k_number(2, None).unwrap();
+
+    let reds: Vec<i32> = result.clusters.iter().map(|c| c.r).collect();
+    assert_eq!(reds, vec![5, 205]);
+}
+
+#[test]
+fn seeded_runs_are_reproducible() {
+    let data = rgba(&[
+        (255, 0, 0),
+        (0, 255, 0),
+        (0, 0, 255),
+        (255, 255, 0),
+        (0, 255, 255),
+        (255, 0, 255),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 2).unwrap();
+    let config = Config {
+        seed: Some(42),
+        ..Config::default()
+    };
+
+    let first = kmeans.with_fixed_k_number(3, Some(config.clone())).unwrap();
+    let second = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+
+    assert!(first.clusters == second.clusters);
+}
+
+#[test]
+fn top_percent_keeps_the_most_frequent_colors() {
+    let data = rgba(&[(255, 0, 0), (255, 0, 0), (255, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    let config = Config {
+        top_percent: Some(0.5),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+
+    assert_eq!(result.ks, 1);
+    assert_eq!(result.clusters[0].r, 255);
+}
+
+#[test]
+fn top_num_and_top_percent_together_is_an_error() {
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&[(255, 0, 0)]), 1, 1).unwrap();
+    let config = Config {
+        top_num: Some(1),
+        top_percent: Some(0.5),
+        ..Config::default()
+    };
+
+    assert!(matches!(
+        kmeans.with_fixed_k_number(1, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn top_num_below_k_is_reported_as_a_warning() {
+    let pixels = [(255, 0, 0), (0, 255, 0), (0, 0, 255), (0, 0, 0)];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 4, 1).unwrap();
+    let config = Config {
+        top_num: Some(3),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(8, Some(config.clone())).unwrap();
+    assert_eq!(result.ks, 3);
+    assert_eq!(
+        result.warnings,
+        vec![RunWarning::TopNumBelowK { top_num: 3, k: 8 }]
+    );
+
+    let result = kmeans.with_fixed_k_number(3, Some(config.clone())).unwrap();
+    assert!(result.warnings.is_empty());
+
+    let results = kmeans.fixed_k_batch(vec![2, 5], Some(config)).unwrap();
+    assert!(results.0[0].warnings.is_empty());
+    assert_eq!(results.0[1].warnings.len(), 1);
+}
+
+#[test]
+fn score_palette_sums_weighted_squared_distances() {
+    let data = rgba(&[(10, 0, 0), (10, 0, 0), (0, 0, 0), (100, 0, 0)]);
+    let kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    let palette = [Color { r: 0, g: 0, b: 0 }, Color { r: 100, g: 0, b: 0 }];
+
+    assert_eq!(kmeans.score_palette(&palette).unwrap(), 200.0);
+    assert_eq!(kmeans.score_palette(&[]).unwrap(), 0.0);
+}
+
+#[test]
+fn trace_records_every_iteration() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    let config = Config {
+        trace: true,
+        ..Config::default()
+    };
+
+    let traced = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let untraced = kmeans.with_fixed_k_number(2, None).unwrap();
+
+    let trace = traced.trace.unwrap();
+    assert!(!trace.is_empty());
+    assert_eq!(trace.last().unwrap().wcss, traced.wcss);
+    assert!(untraced.trace.is_none());
+    assert_eq!(trace.len(), traced.iterations);
+    assert_eq!(trace[0].reassigned, 4);
+    assert_eq!(trace.last().unwrap().reassigned, 0);
+}
+
+#[test]
+fn error_map_gives_a_distance_per_pixel() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    kmeans.set_random_source(Box::new(FirstPick));
+
+    assert!(kmeans.error_map().is_empty());
+
+    kmeans.with_fixed_k_number(2, None).unwrap();
+
+    assert_eq!(kmeans.error_map(), vec![5.0, 5.0, 5.0, 5.0]);
+}
+
+#[test]
+fn error_map_measures_as_the_run_did() {
+    let pixels = [(0, 0, 0), (10, 4, 0), (200, 0, 0), (210, 4, 0)];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 4, 1).unwrap();
+    kmeans.set_random_source(Box::new(FirstPick));
+
+    let config = Config {
+        distance_metric: DistanceMetric::Chebyshev,
+        ..Config::default()
+    };
+    kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    assert_eq!(kmeans.error_map(), vec![5.0, 5.0, 5.0, 5.0]);
+
+    let config = Config {
+        color_space: ColorSpace::Lch,
+        ..Config::default()
+    };
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let expected: Vec<f32> = pixels
+        .iter()
+        .map(|&(r, g, b)| {
+            let color = Color {
+                r: r as i32,
+                g: g as i32,
+                b: b as i32,
+            };
+            result
+                .clusters
+                .iter()
+                .map(|k| color.distance_to(k, ColorSpace::Lch))
+                .fold(f32::INFINITY, f32::min)
+        })
+        .collect();
+    assert_eq!(kmeans.error_map(), expected);
+}
+
+#[test]
+fn wcss_is_weighted_by_pixel_count() {
+    let data = rgba(&[(0, 0, 0), (0, 0, 0), (0, 0, 0), (10, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+
+    let result = kmeans.with_fixed_k_number(1, None).unwrap();
+
+    assert_eq!(result.clusters[0].r, 2);
+    assert_eq!(result.wcss, 76.0);
+}
+
+#[test]
+fn default_config_is_valid() {
+    assert_eq!(Config::default().validate(), Ok(()));
+}
+
+#[test]
+fn validate_describes_the_problem() {
+    let config = Config {
+        top_percent: Some(1.5),
+        ..Config::default()
+    };
+
+    assert_eq!(
+        config.validate(),
+        Err(KmeansError::InvalidConfig {
+            reason: "top_percent (1.5) must be above 0 and at most 1".to_string()
+        })
+    );
+}
+
+#[test]
+fn seeded_derived_runs_are_reproducible() {
+    let data = rgba(&[
+        (255, 0, 0),
+        (250, 5, 0),
+        (0, 255, 0),
+        (5, 250, 0),
+        (0, 0, 255),
+        (0, 5, 250),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 2).unwrap();
+    let config = Config {
+        seed: Some(7),
+        ..Config::default()
+    };
+
+    let first = kmeans.with_derived_k_number(Some(config.clone())).unwrap();
+    let second = kmeans.with_derived_k_number(Some(config)).unwrap();
+
+    assert_eq!(first.ks, second.ks);
+    assert!(first.clusters == second.clusters);
+    assert_eq!(first.wcss, second.wcss);
+}
+
+#[test]
+fn min_separation_spreads_the_clusters() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (20, 0, 0), (200, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    let config = Config {
+        min_separation: Some(15.0),
+        seed: Some(3),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+
+    for (i, a) in result.clusters.iter().enumerate() {
+        for b in &result.clusters[i + 1..] {
+            assert!((a.r - b.r).abs() >= 15);
+        }
+    }
+}
+
+#[test]
+fn min_separation_is_measured_in_the_color_space() {
+    let mut pixels = vec![(0, 0, 70); 5];
+    pixels.extend([(0, 0, 80); 5]);
+    pixels.extend([(0, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 12, 1).unwrap();
+    // the navies are at least 70 from black in RGB but under 60 in LCh
+    let separated = |kmeans: &mut ImageKmeans, color_space| {
+        let config = Config {
+            color_space,
+            locked_colors: vec![Color { r: 0, g: 0, b: 0 }],
+            min_separation: Some(60.0),
+            seed: Some(1),
+            ..Config::default()
+        };
+        let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+        (
+            result.clusters[1].r,
+            result.clusters[1].g,
+            result.clusters[1].b,
+        )
+    };
+
+    assert_eq!(separated(&mut kmeans, ColorSpace::Rgb), (0, 0, 80));
+    assert_eq!(separated(&mut kmeans, ColorSpace::Lch), (0, 0, 255));
+}
+
+#[test]
+fn lch_clusters_round_trip_to_srgb() {
+    let data = rgba(&[(200, 40, 90), (200, 40, 90)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+    let config = Config {
+        color_space: ColorSpace::Lch,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+
+    assert!(
+        result.clusters
+            == vec![Color {
+                r: 200,
+                g: 40,
+                b: 90
+            }]
+    );
+}
+
+#[test]
+fn lch_hue_mean_wraps_around_zero() {
+    let data = rgba(&[(255, 0, 60), (255, 60, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+    let config = Config {
+        color_space: ColorSpace::Lch,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+
+    let red = &result.clusters[0];
+    assert!(red.r > 200 && red.g < 100 && red.b < 100);
+}
+
+#[test]
+fn seeding_methods_pick_from_separate_groups() {
+    let data = rgba(&[(0, 0, 0), (2, 0, 0), (250, 0, 0), (252, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+
+    for init in [InitMethod::KmeansPlusPlus, InitMethod::KmeansParallel] {
+        let config = Config {
+            init,
+            seed: Some(11),
+            ..Config::default()
+        };
+
+        let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+
+        let mut reds: Vec<i32> = result.clusters.iter().map(|c| c.r).collect();
+        reds.sort();
+        assert_eq!(reds, vec![1, 251]);
+    }
+}
+
+#[test]
+fn compare_inits_runs_both_methods_with_the_same_seed() {
+    let pixels: Vec<(u8, u8, u8)> = (0..64)
+        .map(|i| ((i * 37 % 256) as u8, (i * 11 % 256) as u8, (i * 5) as u8))
+        .collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 8, 8).unwrap();
+    let config = Config {
+        seed: Some(3),
+        ..Config::default()
+    };
+
+    let (random, plus_plus) = kmeans.compare_inits(4, Some(config.clone())).unwrap();
+    let best = if plus_plus.wcss <= random.wcss {
+        &plus_plus
+    } else {
+        &random
+    };
+    let latest: Vec<_> = kmeans
+        .palette_iter()
+        .map(|e| (e.color.r, e.color.g, e.color.b))
+        .collect();
+    let best: Vec<_> = best.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    assert_eq!(latest, best);
+
+    for (init, compared) in [
+        (InitMethod::Random, random),
+        (InitMethod::KmeansPlusPlus, plus_plus),
+    ] {
+        let config = Config {
+            init,
+            ..config.clone()
+        };
+        let result = kmeans.with_fixed_k_number(4, Some(config)).unwrap();
+        assert!(result.clusters == compared.clusters);
+        assert_eq!(result.wcss, compared.wcss);
+    }
+}
+
+#[test]
+fn kmeans_parallel_picks_k_distinct_colors_from_rich_images() {
+    let pixels: Vec<(u8, u8, u8)> = (0..1024)
+        .map(|i| ((i % 256) as u8, (i / 4) as u8, (i * 7 % 256) as u8))
+        .collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 32, 32).unwrap();
+    let config = Config {
+        init: InitMethod::KmeansParallel,
+        seed: Some(5),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(8, Some(config)).unwrap();
+
+    assert_eq!(result.ks, 8);
+    assert_eq!(result.clusters.len(), 8);
+}
+
+#[test]
+fn elbow_curve_has_a_point_per_derived_run() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+
+    assert!(kmeans.elbow_curve().points.is_empty());
+
+    let result = kmeans.with_derived_k_number(None).unwrap();
+    let curve = kmeans.elbow_curve();
+
+    let ks: Vec<usize> = curve.points.iter().map(|p| p.0).collect();
+    assert_eq!(ks, vec![1, 2, 3, 4]);
+    assert_eq!(curve.points[curve.selected], (result.ks, result.wcss));
+}
+
+#[test]
+fn merge_delta_e_folds_near_identical_colors() {
+    let data = rgba(&[(200, 0, 0), (200, 0, 0), (201, 0, 0), (0, 0, 200)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    let config = Config {
+        merge_delta_e: Some(2.0),
+        snap_only_final: true,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+
+    assert_eq!(result.ks, 2);
+    assert!(result.clusters.contains(&Color { r: 200, g: 0, b: 0 }));
+    assert_eq!(
+        kmeans
+            .score_palette(&[Color { r: 200, g: 0, b: 0 }])
+            .unwrap(),
+        80000.0
+    );
+}
+
+#[test]
+fn palette_normalized_divides_channels_by_255() {
+    let data = rgba(&[(255, 0, 51), (255, 0, 51)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+
+    kmeans.with_fixed_k_number(1, None).unwrap();
+
+    assert_eq!(kmeans.palette_normalized().0, vec![[1.0, 0.0, 0.2]]);
+}
+
+#[test]
+fn derived_k_on_a_solid_color_is_a_single_cluster() {
+    let data = rgba(&[(255, 0, 0); 16]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 4).unwrap();
+
+    let result = kmeans.with_derived_k_number(None).unwrap();
+
+    assert_eq!(result.ks, 1);
+    assert!(result.clusters == vec![Color { r: 255, g: 0, b: 0 }]);
+    assert_eq!(result.wcss, 0.0);
+}
+
+#[test]
+fn single_color_working_sets_give_one_cluster_for_every_init() {
+    let solid = rgba(&[(255, 0, 0); 16]);
+    let near = rgba(&[(200, 10, 10), (201, 11, 10), (202, 10, 11), (203, 11, 11)]);
+    let cases = [
+        (solid, None, None),
+        (near.clone(), Some(1), None),
+        (near, None, Some(16)),
+    ];
+
+    for (data, top_num, quantize_fact) in cases {
+        let mut kmeans = ImageKmeans::from_rgba(&data, 4, data.len() as u32 / 16).unwrap();
+        for init in [InitMethod::Random, InitMethod::KmeansPlusPlus] {
+            let config = Config {
+                init,
+                top_num,
+                quantize_fact,
+                ..Config::default()
+            };
+
+            let result = kmeans.with_fixed_k_number(4, Some(config)).unwrap();
+
+            assert_eq!((result.ks, result.distinct), (1, 1));
+            assert_eq!(result.clusters.len(), 1);
+            assert_eq!(result.wcss, 0.0);
+        }
+    }
+}
+
+#[test]
+fn chebyshev_metric_assigns_by_the_largest_channel_difference() {
+    let data = rgba(&[(0, 0, 0), (180, 80, 80), (100, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 1).unwrap();
+    let config = Config {
+        locked_colors: vec![
+            Color { r: 0, g: 0, b: 0 },
+            Color {
+                r: 180,
+                g: 80,
+                b: 80,
+            },
+        ],
+        ..Config::default()
+    };
+
+    // (100, 0, 0) is 100 from black either way, but 80 from the other color
+    // in every channel, which is further in a straight line
+    let result = kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    assert_eq!((result.counts.clone(), result.wcss), (vec![2, 1], 10000.0));
+
+    let config = Config {
+        distance_metric: DistanceMetric::Chebyshev,
+        ..config
+    };
+    let result = kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    assert_eq!((result.counts.clone(), result.wcss), (vec![1, 2], 6400.0));
+
+    let config = Config {
+        color_space: ColorSpace::Lch,
+        ..config
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(2, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn pixel_at_reads_the_stored_image_within_its_dimensions() {
+    let data = rgba(&[
+        (1, 0, 0),
+        (2, 0, 0),
+        (3, 0, 0),
+        (4, 0, 0),
+        (5, 0, 0),
+        (6, 0, 0),
+    ]);
+    let kmeans = ImageKmeans::from_rgba(&data, 3, 2).unwrap();
+
+    assert_eq!(kmeans.dimensions(), (3, 2));
+    assert_eq!(kmeans.pixel_at(0, 0).map(|c| c.r), Some(1));
+    assert_eq!(kmeans.pixel_at(2, 0).map(|c| c.r), Some(3));
+    assert_eq!(kmeans.pixel_at(1, 1).map(|c| c.r), Some(5));
+    assert!(kmeans.pixel_at(3, 0).is_none());
+    assert!(kmeans.pixel_at(0, 2).is_none());
+
+    let data = rgba(&[(9, 9, 9); 16]);
+    let capped = ImageKmeans::from_rgba_capped(&data, 4, 4, Some(4)).unwrap();
+    let (width, height) = capped.dimensions();
+    assert_eq!((width * height) as f32 / 16.0, capped.sample_rate());
+    assert!(capped.pixel_at(width - 1, height - 1).is_some());
+    assert!(capped.pixel_at(width, 0).is_none());
+}
+
+#[test]
+fn name_palette_labels_clusters_with_the_nearest_named_color() {
+    let data = rgba(&[(250, 10, 20), (250, 10, 20), (10, 10, 120)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 1).unwrap();
+    let named = vec![
+        ("Black".to_string(), Color { r: 0, g: 0, b: 0 }),
+        (
+            "Crimson".to_string(),
+            Color {
+                r: 220,
+                g: 20,
+                b: 60,
+            },
+        ),
+        ("Navy".to_string(), Color { r: 0, g: 0, b: 128 }),
+    ];
+
+    assert!(kmeans.name_palette(&named).unwrap().is_empty());
+
+    let result = kmeans.with_fixed_k_number(2, None).unwrap();
+    let names = kmeans.name_palette(&named).unwrap();
+    assert_eq!(names.len(), 2);
+    for (cluster, (name, color, distance)) in result.clusters.iter().zip(&names) {
+        let expected = if cluster.r == 250 { "Crimson" } else { "Navy" };
+        assert_eq!(name, expected);
+        assert!(named.iter().any(|(n, c)| n == name && c == color));
+        assert!(*distance > 0.0 && *distance < 40.0);
+    }
+
+    let bad = vec![("Bad".to_string(), Color { r: 256, g: 0, b: 0 })];
+    assert!(matches!(
+        kmeans.name_palette(&bad),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn max_working_colors_folds_rare_colors_into_kept_ones() {
+    let mut pixels = vec![(0, 0, 0); 3];
+    pixels.extend([
+        (10, 0, 0),
+        (255, 255, 255),
+        (255, 255, 255),
+        (250, 250, 250),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 7, 1).unwrap();
+    let config = Config {
+        seed: Some(1),
+        ..Config::default()
+    };
+
+    let capped = Config {
+        max_working_colors: Some(2),
+        ..config.clone()
+    };
+    let result = kmeans.with_fixed_k_number(4, Some(capped)).unwrap();
+    let mut found: Vec<_> = result
+        .clusters
+        .iter()
+        .zip(&result.counts)
+        .map(|(c, &n)| ((c.r, c.g, c.b), n))
+        .collect();
+    found.sort();
+    assert_eq!(found, vec![((0, 0, 0), 4), ((255, 255, 255), 3)]);
+    assert_eq!(result.wcss, 0.0);
+
+    // top_num drops the rare colors' pixels instead
+    let limited = Config {
+        top_num: Some(2),
+        ..config
+    };
+    let result = kmeans.with_fixed_k_number(4, Some(limited)).unwrap();
+    assert_eq!(result.counts.iter().sum::<usize>(), 5);
+
+    let invalid = Config {
+        max_working_colors: Some(0),
+        ..Config::default()
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(2, Some(invalid)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn direct_working_colors_are_clustered_without_an_image() {
+    let mut kmeans = ImageKmeans::new_empty();
+    assert!(matches!(
+        kmeans.with_fixed_k_number(2, None),
+        Err(KmeansError::NoColors)
+    ));
+
+    let black = Color { r: 0, g: 0, b: 0 };
+    let reds = [Color { r: 255, g: 0, b: 0 }, Color { r: 250, g: 0, b: 0 }];
+    let counts = BTreeMap::from([(black.clone(), 5)]);
+    kmeans.set_working_colors_direct(vec![black, reds[0].clone(), reds[1].clone()], counts);
+
+    for init in [InitMethod::Random, InitMethod::KmeansPlusPlus] {
+        let config = Config {
+            init,
+            seed: Some(4),
+            ..Config::default()
+        };
+        let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+
+        let mut found: Vec<_> = result
+            .clusters
+            .iter()
+            .zip(&result.counts)
+            .map(|(c, &n)| (c.r, n))
+            .collect();
+        found.sort();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0], (0, 5));
+        assert_eq!(found[1].1, 2);
+        assert_eq!(result.wcss, 13.0);
+    }
+}
+
+#[test]
+fn contrast_ratio_of_black_and_white_is_21() {
+    let black = Color { r: 0, g: 0, b: 0 };
+    let white = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    assert!((black.contrast_ratio(&white) - 21.0).abs() < 1e-4);
+    assert_eq!(white.contrast_ratio(&white), 1.0);
+}
+
+#[test]
+fn contrast_ratios_covers_every_cluster_pair() {
+    let data = rgba(&[(0, 0, 0), (255, 255, 255), (255, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 1).unwrap();
+
+    kmeans.with_fixed_k_number(3, None).unwrap();
+
+    let pairs: Vec<(usize, usize)> = kmeans.contrast_ratios().0.iter().map(|p| p.0).collect();
+    assert_eq!(pairs, vec![(0, 1), (0, 2), (1, 2)]);
+}
+
+#[test]
+fn added_images_are_clustered_together() {
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&[(0, 0, 0)]), 1, 1).unwrap();
+    kmeans
+        .add_rgba(&rgba(&[(40, 0, 0), (40, 0, 0), (40, 0, 0)]), 3, 1)
+        .unwrap();
+
+    let result = kmeans.with_fixed_k_number(1, None).unwrap();
+
+    assert_eq!(result.clusters[0].r, 30);
+    assert_eq!(
+        kmeans.add_rgba(&[0, 0, 0], 1, 1),
+        Err(KmeansError::InvalidDimensions)
+    );
+}
+
+#[test]
+fn target_distinct_picks_a_quantization_factor() {
+    let pixels: Vec<(u8, u8, u8)> = (0..64).map(|i| (i * 4, 0, 0)).collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 8, 8).unwrap();
+    let config = Config {
+        target_distinct: Some(4),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(10, Some(config)).unwrap();
+
+    assert_eq!(kmeans.quantize_fact(), 64);
+    assert_eq!(result.ks, 4);
+}
+
+#[test]
+fn palette_hex_is_zero_padded_uppercase() {
+    let data = rgba(&[(10, 171, 0), (10, 171, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+
+    assert!(kmeans.palette_hex().is_empty());
+
+    kmeans.with_fixed_k_number(1, None).unwrap();
+
+    assert_eq!(kmeans.palette_hex(), vec!["#0AAB00".to_string()]);
+}
+
+#[test]
+fn kmedoids_clusters_are_image_colors() {
+    let data = rgba(&[
+        (0, 0, 0),
+        (10, 0, 0),
+        (30, 0, 0),
+        (200, 200, 200),
+        (250, 250, 250),
+        (255, 255, 255),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 6, 1).unwrap();
+    let config = Config {
+        algorithm: Algorithm::KMedoids,
+        seed: Some(3),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    assert_eq!(clusters, vec![(10, 0, 0), (250, 250, 250)]);
+    assert!((result.wcss - (30.0 + 55.0 * 3.0_f32.sqrt())).abs() < 1e-3);
+}
+
+#[test]
+fn locked_colors_stay_in_the_palette() {
+    let data = rgba(&[(0, 0, 0), (20, 0, 0), (200, 200, 200), (220, 220, 220)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    let config = Config {
+        locked_colors: vec![Color { r: 255, g: 0, b: 0 }],
+        seed: Some(1),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config.clone())).unwrap();
+
+    assert_eq!(result.ks, 3);
+    assert_eq!(
+        (
+            result.clusters[0].r,
+            result.clusters[0].g,
+            result.clusters[0].b
+        ),
+        (255, 0, 0)
+    );
+    assert!(matches!(
+        kmeans.with_fixed_k_number(0, Some(config.clone())),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+    let config = Config {
+        locked_colors: vec![Color { r: 0, g: 0, b: 0 }; 2],
+        ..config
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(1, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn recolored_rgba_keeps_original_alpha() {
+    let data: Vec<u8> = (0..16u8)
+        .flat_map(|i| {
+            let alpha = if (i % 4 + i / 4) % 2 == 0 { 0 } else { 255 - i };
+            [i * 10, 0, 0, alpha]
+        })
+        .collect();
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 4).unwrap();
+
+    kmeans.with_fixed_k_number(2, None).unwrap();
+    let recolored = kmeans.recolored_rgba();
+
+    assert_eq!(recolored.len(), data.len());
+    let alpha = |bytes: &[u8]| bytes.chunks(4).map(|px| px[3]).collect::<Vec<u8>>();
+    assert_eq!(alpha(&recolored), alpha(&data));
+}
+
+#[test]
+fn collided_clusters_are_reported_or_reseeded() {
+    let data = rgba(&[(0, 0, 0), (0, 0, 0), (0, 0, 0), (255, 255, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    kmeans.set_random_source(Box::new(FirstPick));
+    let config = Config {
+        locked_colors: vec![Color { r: 0, g: 0, b: 0 }],
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    assert_eq!((result.ks, result.distinct), (2, 1));
+
+    let config = Config {
+        collision: CollisionMode::Reseed,
+        ..config
+    };
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    assert_eq!((result.ks, result.distinct), (2, 2));
+    assert_eq!(result.clusters[1].b, 255);
+    assert_eq!(result.wcss, 0.0);
+}
+
+#[test]
+fn guarantee_k_gives_k_distinct_real_colors() {
+    let data = rgba(&[(0, 0, 0), (0, 0, 0), (0, 0, 0), (255, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 5, 1).unwrap();
+    kmeans.set_random_source(Box::new(FirstPick));
+    let config = Config {
+        locked_colors: vec![Color { r: 0, g: 0, b: 0 }],
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config.clone())).unwrap();
+    assert!(result.distinct < 3);
+
+    let config = Config {
+        guarantee_k: true,
+        ..config
+    };
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+    let mut colors: Vec<_> = result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    colors.sort();
+    assert_eq!(colors, vec![(0, 0, 0), (0, 0, 255), (255, 0, 0)]);
+    assert_eq!((result.ks, result.distinct), (3, 3));
+    assert_eq!(result.counts, vec![3, 1, 1]);
+    assert_eq!(result.wcss, 0.0);
+
+    let config = Config {
+        guarantee_k: true,
+        min_cluster_fraction: Some(0.1),
+        ..Config::default()
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(3, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn variance_explained_shares_the_reduction_in_wcss() {
+    let data = rgba(&[(0, 0, 0), (0, 0, 0), (0, 0, 0), (255, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    let config = Config {
+        seed: Some(1),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    let shares: Vec<_> = result
+        .clusters
+        .iter()
+        .zip(&result.variance_explained)
+        .map(|(c, share)| ((c.r, c.g, c.b), *share))
+        .collect();
+    assert_eq!(shares.len(), 2);
+    for (color, share) in shares {
+        let expected = if color == (0, 0, 0) { 0.25 } else { 0.75 };
+        assert!((share - expected).abs() < 1e-6, "{:?} {}", color, share);
+    }
+
+    let result = kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+    assert_eq!(result.variance_explained, vec![0.0]);
+}
+
+#[test]
+fn palette_ranked_by_sorts_clusters_by_a_custom_score() {
+    let data = rgba(&[(0, 0, 0), (0, 0, 0), (0, 0, 0), (255, 0, 0), (0, 0, 200)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 5, 1).unwrap();
+    assert!(kmeans.palette_ranked_by(|_, count| count as f32).is_empty());
+
+    kmeans.with_fixed_k_number(3, None).unwrap();
+    let by_count: Vec<_> = kmeans
+        .palette_ranked_by(|_, count| count as f32)
+        .into_iter()
+        .map(|(c, n)| ((c.r, c.g, c.b), n))
+        .collect();
+    assert_eq!(by_count[0], ((0, 0, 0), 3));
+
+    let by_blue: Vec<_> = kmeans
+        .palette_ranked_by(|c, _| c.b as f32)
+        .into_iter()
+        .map(|(c, n)| ((c.r, c.g, c.b), n))
+        .collect();
+    assert_eq!(by_blue[0], ((0, 0, 200), 1));
+    assert_eq!(by_blue.len(), 3);
+}
+
+#[test]
+fn dominant_and_rare_use_cluster_counts() {
+    let data = rgba(&[(0, 0, 0), (0, 0, 0), (0, 0, 0), (255, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 5, 1).unwrap();
+
+    assert!(kmeans.dominant_and_rare().is_none());
+
+    let result = kmeans
+        .with_fixed_k_number(
+            3,
+            Some(Config {
+                seed: Some(2),
+                ..Config::default()
+            }),
+        )
+        .unwrap();
+    let (dominant, rare) = kmeans.dominant_and_rare().unwrap();
+
+    assert_eq!(result.counts.iter().sum::<usize>(), 5);
+    assert_eq!((dominant.r, dominant.g, dominant.b), (0, 0, 0));
+    // the two single pixel clusters tie, the earliest wins
+    let first_single = result.counts.iter().position(|&c| c == 1).unwrap();
+    assert!(rare == result.clusters[first_single]);
+
+    kmeans.with_fixed_k_number(1, None).unwrap();
+    let (dominant, rare) = kmeans.dominant_and_rare().unwrap();
+    assert!(dominant == rare);
+}
+
+#[test]
+fn max_colors_samples_large_images() {
+    let pixels: Vec<(u8, u8, u8)> = (0..100).map(|i| (i, 0, 0)).collect();
+    let data = rgba(&pixels);
+
+    let kmeans = ImageKmeans::from_rgba_capped(&data, 10, 10, Some(30)).unwrap();
+    assert_eq!(kmeans.sample_rate(), 0.25);
+    assert_eq!(kmeans.recolored_rgba().len(), 25 * 4);
+
+    let kmeans = ImageKmeans::from_rgba_capped(&data, 10, 10, Some(100)).unwrap();
+    assert_eq!(kmeans.sample_rate(), 1.0);
+
+    assert!(matches!(
+        ImageKmeans::from_rgba_capped(&data, 10, 10, Some(0)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn blend_interpolates_and_clamps() {
+    let black = Color { r: 0, g: 0, b: 0 };
+    let white = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    assert_eq!(Color::blend(&black, &white, 0.5).r, 128);
+    assert_eq!(Color::blend(&black, &white, 2.0).r, 255);
+    assert_eq!(Color::blend_linear(&black, &white, 0.5).r, 188);
+}
+
+#[test]
+fn palette_gradient_is_monotone_in_luminance() {
+    let data = rgba(&[(250, 250, 250), (0, 0, 0), (200, 0, 0), (0, 0, 120)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+
+    assert!(kmeans.palette_gradient(5).0.is_empty());
+
+    kmeans.with_fixed_k_number(4, None).unwrap();
+    let ramp = kmeans.palette_gradient(16).0;
+
+    assert_eq!(ramp.len(), 16);
+    assert_eq!((ramp[0].r, ramp[0].g, ramp[0].b), (0, 0, 0));
+    assert_eq!((ramp[15].r, ramp[15].g, ramp[15].b), (250, 250, 250));
+    assert!(ramp
+        .windows(2)
+        .all(|w| w[0].relative_luminance() <= w[1].relative_luminance() + 1e-3));
+}
+
+#[test]
+fn seeded_runs_are_cached_until_an_image_is_added() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    let config = Config {
+        seed: Some(9),
+        ..Config::default()
+    };
+
+    let first = kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    let repeat = kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    assert!(first.clusters == repeat.clusters);
+
+    kmeans
+        .add_rgba(&rgba(&[(0, 0, 250), (0, 0, 250)]), 2, 1)
+        .unwrap();
+    let after = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    assert!(after.clusters.iter().any(|c| c.b > 0));
+}
+
+#[test]
+fn quantize_stage_orders_top_num_and_quantizing() {
+    let data = rgba(&[
+        (100, 0, 0),
+        (100, 0, 0),
+        (100, 0, 0),
+        (1, 0, 0),
+        (2, 0, 0),
+        (3, 0, 0),
+        (4, 0, 0),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 7, 1).unwrap();
+    let config = Config {
+        quantize_fact: Some(16),
+        top_num: Some(1),
+        ..Config::default()
+    };
+
+    let before = kmeans.with_fixed_k_number(1, Some(config.clone())).unwrap();
+    assert_eq!(before.clusters[0].r, 0);
+
+    let config = Config {
+        quantize_stage: QuantizeStage::AfterTopN,
+        ..config
+    };
+    let after = kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+    assert_eq!(after.clusters[0].r, 96);
+}
+
+#[test]
+fn color_entropy_is_in_bits() {
+    let flat = ImageKmeans::from_rgba(&rgba(&[(5, 5, 5), (5, 5, 5)]), 2, 1).unwrap();
+    assert_eq!(flat.color_entropy(), 0.0);
+
+    let data = rgba(&[(0, 0, 0), (255, 0, 0), (0, 255, 0), (0, 0, 255)]);
+    let four = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    assert!((four.color_entropy() - 2.0).abs() < 1e-6);
+}
+
+#[test]
+fn deterministic_init_spaces_picks_by_frequency() {
+    let data = rgba(&[
+        (0, 0, 0),
+        (0, 0, 0),
+        (0, 0, 0),
+        (100, 0, 0),
+        (200, 0, 0),
+        (250, 0, 0),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 6, 1).unwrap();
+    let config = Config {
+        init: InitMethod::Deterministic,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+
+    let reds: Vec<i32> = result.clusters.iter().map(|c| c.r).collect();
+    assert_eq!(reds, vec![0, 100, 225]);
+}
+
+#[test]
+fn raw_histogram_ignores_quantizing() {
+    let data = rgba(&[(1, 0, 0), (2, 0, 0), (2, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 1).unwrap();
+    let config = Config {
+        quantize_fact: Some(16),
+        ..Config::default()
+    };
+
+    kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+    let counts: Vec<(i32, usize)> = kmeans
+        .raw_histogram()
+        .into_iter()
+        .map(|(c, n)| (c.r, n))
+        .collect();
+
+    assert_eq!(counts, vec![(1, 1), (2, 2)]);
+}
+
+#[test]
+fn hilbert_splits_sorted_colors_at_the_largest_jumps() {
+    let data = rgba(&[(0, 0, 0), (2, 0, 0), (250, 250, 250), (252, 250, 250)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    let config = Config {
+        algorithm: Algorithm::Hilbert1D,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    assert_eq!(clusters, vec![(1, 0, 0), (251, 250, 250)]);
+    assert_eq!(result.counts, vec![2, 2]);
+}
+
+#[test]
+fn selection_detail_explains_derived_k() {
+    let pixels: Vec<(u8, u8, u8)> = (0..40).map(|i| ((i % 4) * 80, 0, 0)).collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 40, 1).unwrap();
+
+    assert!(kmeans.selection_detail().is_none());
+
+    let result = kmeans.with_derived_k_number(None).unwrap();
+    let detail = kmeans.selection_detail().unwrap();
+
+    assert_eq!(detail.chosen_k, result.ks);
+    assert_eq!(detail.distances.len(), kmeans.elbow_curve().points.len());
+    let max = detail.distances.iter().cloned().fold(f32::MIN, f32::max);
+    assert_eq!(detail.chosen_distance, max);
+
+    kmeans.with_fixed_k_number(2, None).unwrap();
+    assert!(kmeans.selection_detail().is_none());
+}
+
+#[test]
+fn alpha_mode_controls_pixel_counts() {
+    let data = [
+        255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 0, 255, 255, 0, 0, 255, 128,
+    ];
+    let mut kmeans = ImageKmeans::from_rgba(&data, 5, 1).unwrap();
+    let config = |alpha_mode| Config {
+        alpha_mode,
+        ..Config::default()
+    };
+
+    let result = kmeans
+        .with_fixed_k_number(2, Some(config(AlphaMode::Ignore)))
+        .unwrap();
+    assert_eq!(result.counts.iter().sum::<usize>(), 5);
+
+    let result = kmeans
+        .with_fixed_k_number(1, Some(config(AlphaMode::SkipTransparent)))
+        .unwrap();
+    assert_eq!((result.clusters[0].r, result.clusters[0].b), (0, 255));
+    assert_eq!(result.counts, vec![2]);
+
+    let result = kmeans
+        .with_fixed_k_number(1, Some(config(AlphaMode::Weighted)))
+        .unwrap();
+    assert_eq!(result.counts, vec![255 + 128]);
+
+    let transparent = [0, 0, 0, 0];
+    let mut kmeans = ImageKmeans::from_rgba(&transparent, 1, 1).unwrap();
+    assert!(matches!(
+        kmeans.with_fixed_k_number(1, Some(config(AlphaMode::SkipTransparent))),
+        Err(KmeansError::NoColors)
+    ));
+}
+
+#[test]
+fn moving_top_num_reuses_the_histogram() {
+    let data = rgba(&[
+        (0, 0, 0),
+        (0, 0, 0),
+        (0, 0, 0),
+        (90, 0, 0),
+        (90, 0, 0),
+        (200, 0, 0),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 6, 1).unwrap();
+    let top = |top_num| Config {
+        top_num,
+        ..Config::default()
+    };
+
+    let counts = |kmeans: &mut ImageKmeans, config| {
+        let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+        result.counts.iter().sum::<usize>()
+    };
+
+    assert_eq!(counts(&mut kmeans, top(Some(3))), 6);
+    assert_eq!(counts(&mut kmeans, top(Some(1))), 3);
+    assert_eq!(counts(&mut kmeans, top(Some(2))), 5);
+    assert_eq!(counts(&mut kmeans, top(None)), 6);
+}
+
+#[test]
+fn debug_state_summarizes_without_pixels() {
+    let data = rgba(&[(0, 0, 0), (0, 0, 0), (255, 0, 0), (255, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    kmeans.with_fixed_k_number(2, None).unwrap();
+
+    let state = kmeans.debug_state();
+
+    assert!(state.contains("\"distinct_colors\":2"));
+    assert!(state.contains("\"results\":[{\"ks\":2"));
+    assert!(!state.contains("\"colors\""));
+}
+
+#[test]
+fn palette_entries_combine_hex_and_counts() {
+    let data = rgba(&[(255, 0, 0), (255, 0, 0), (255, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+
+    assert!(kmeans.palette_json_with_counts().0.is_empty());
+
+    kmeans.with_fixed_k_number(2, None).unwrap();
+    let mut entries: Vec<(String, usize, f32)> = kmeans
+        .palette_json_with_counts()
+        .0
+        .into_iter()
+        .map(|e| (e.hex, e.count, e.fraction))
+        .collect();
+    entries.sort_by_key(|e| e.1);
+
+    assert_eq!(
+        entries,
+        vec![
+            ("#0000FF".to_string(), 1, 0.25),
+            ("#FF0000".to_string(), 3, 0.75)
+        ]
+    );
+}
+
+#[test]
+fn min_cluster_fraction_drops_tiny_clusters() {
+    let mut pixels = vec![(0, 0, 0); 10];
+    pixels.extend(vec![(255, 255, 255); 10]);
+    pixels.push((255, 0, 0));
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 21, 1).unwrap();
+    let config = Config {
+        init: InitMethod::Deterministic,
+        min_cluster_fraction: Some(0.1),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+
+    assert_eq!(result.ks, 2);
+    assert_eq!(result.counts.iter().sum::<usize>(), 21);
+    assert!(result.clusters.iter().all(|c| c.g == c.b));
+}
+
+#[test]
+fn recount_rgba_uses_the_full_resolution_image() {
+    let thumbnail = rgba(&[(0, 0, 0), (250, 250, 250)]);
+    let mut kmeans = ImageKmeans::from_rgba(&thumbnail, 2, 1).unwrap();
+    let full = rgba(&[(0, 0, 0), (5, 5, 5), (10, 10, 10), (250, 250, 250)]);
+
+    assert!(matches!(
+        kmeans.recount_rgba(&full, 2, 2),
+        Err(KmeansError::NoRun)
+    ));
+
+    let result = kmeans
+        .with_fixed_k_number(
+            2,
+            Some(Config {
+                init: InitMethod::Deterministic,
+                ..Config::default()
+            }),
+        )
+        .unwrap();
+    assert_eq!(result.counts, vec![1, 1]);
+
+    let recounted = kmeans.recount_rgba(&full, 2, 2).unwrap();
+    let dark = recounted.clusters.iter().position(|c| c.r == 0).unwrap();
+    assert_eq!(recounted.counts[dark], 3);
+    assert_eq!(recounted.counts[1 - dark], 1);
+}
+
+#[test]
+fn recount_rgba_assigns_as_the_run_did() {
+    let pixels: Vec<(u8, u8, u8)> = (0..64_u32)
+        .map(|i| {
+            (
+                (i * 37 % 256) as u8,
+                (i * 91 % 256) as u8,
+                (i * 13 % 256) as u8,
+            )
+        })
+        .collect();
+    let data = rgba(&pixels);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 8, 8).unwrap();
+
+    for config in [
+        Config {
+            color_space: ColorSpace::Lch,
+            seed: Some(3),
+            ..Config::default()
+        },
+        Config {
+            distance_metric: DistanceMetric::Chebyshev,
+            seed: Some(3),
+            ..Config::default()
+        },
+    ] {
+        let result = kmeans.with_fixed_k_number(5, Some(config)).unwrap();
+        let recounted = kmeans.recount_rgba(&data, 8, 8).unwrap();
+        assert_eq!(recounted.counts, result.counts);
+    }
+}
+
+#[test]
+fn color_theme_counts_hue_groups() {
+    let theme = |pixels: &[(u8, u8, u8)]| {
+        let mut kmeans = ImageKmeans::from_rgba(&rgba(pixels), pixels.len() as u32, 1).unwrap();
+        assert!(kmeans.color_theme().is_none());
+        let config = Config {
+            init: InitMethod::Deterministic,
+            ..Config::default()
+        };
+        kmeans
+            .with_fixed_k_number(pixels.len(), Some(config))
+            .unwrap();
+        kmeans.color_theme().unwrap()
+    };
+
+    assert_eq!(theme(&[(0, 0, 0), (255, 255, 255)]), ColorTheme::Monochrome);
+    assert_eq!(theme(&[(255, 0, 0), (120, 0, 0)]), ColorTheme::Monochrome);
+    assert_eq!(
+        theme(&[(255, 0, 0), (0, 0, 255), (0, 0, 0)]),
+        ColorTheme::Duotone
+    );
+    assert_eq!(
+        theme(&[(255, 0, 0), (0, 255, 0), (0, 0, 255)]),
+        ColorTheme::Polychrome
+    );
+}
+
+#[test]
+fn palette_summary_weights_colors_by_count() {
+    let pixels = [(255, 0, 0), (255, 0, 0), (255, 0, 0), (0, 0, 255)];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 4, 1).unwrap();
+    assert_eq!(kmeans.palette_summary(), PaletteSummary::default());
+
+    let config = Config {
+        init: InitMethod::Deterministic,
+        ..Config::default()
+    };
+    kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    let summary = kmeans.palette_summary();
+    assert!((summary.warmth - 0.75).abs() < 1e-6);
+    assert!((summary.saturation - 1.0).abs() < 1e-6);
+    // red and blue have an LCh lightness near 53 and 32
+    assert!((summary.lightness - (0.75 * 0.532 + 0.25 * 0.323)).abs() < 0.01);
+
+    let pixels = [(0, 0, 0), (255, 255, 255)];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 2, 1).unwrap();
+    kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let summary = kmeans.palette_summary();
+    assert_eq!((summary.warmth, summary.saturation), (0.0, 0.0));
+    assert!((summary.lightness - 0.5).abs() < 1e-3);
+}
+
+#[test]
+fn trimmed_mean_ignores_outlying_colors() {
+    let mut pixels = vec![(100, 0, 0); 9];
+    pixels.push((200, 0, 0));
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 10, 1).unwrap();
+
+    let plain = kmeans.with_fixed_k_number(1, None).unwrap();
+    assert_eq!(plain.clusters[0].r, 110);
+
+    let config = Config {
+        trimmed_mean_fraction: Some(0.1),
+        ..Config::default()
+    };
+    let trimmed = kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+    assert_eq!(trimmed.clusters[0].r, 100);
+}
+
+#[test]
+fn geometric_median_centroids_resist_outliers() {
+    let mut pixels = vec![(100, 0, 0); 9];
+    pixels.push((200, 0, 0));
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 10, 1).unwrap();
+    let config = Config {
+        centroid_mode: CentroidMode::GeometricMedian,
+        ..Config::default()
+    };
+
+    let median = kmeans.with_fixed_k_number(1, Some(config.clone())).unwrap();
+    assert_eq!(median.clusters[0].r, 100);
+
+    // the Fermat point of a right angled triangle
+    let pixels = [(0, 0, 0), (100, 0, 0), (0, 100, 0)];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 3, 1).unwrap();
+    let median = kmeans.with_fixed_k_number(1, Some(config.clone())).unwrap();
+    let c = &median.clusters[0];
+    assert!((20..=22).contains(&c.r) && (20..=22).contains(&c.g) && c.b == 0);
+
+    let config = Config {
+        trimmed_mean_fraction: Some(0.1),
+        ..config
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(1, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn working_colors_show_what_was_clustered() {
+    let pixels = [(10, 0, 0), (12, 0, 0), (11, 0, 0), (200, 0, 0)];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 4, 1).unwrap();
+    assert_eq!(kmeans.working_colors().len(), 4);
+
+    let config = Config {
+        quantize_fact: Some(8),
+        ..Config::default()
+    };
+    kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let counts: Vec<_> = kmeans
+        .working_color_counts()
+        .into_iter()
+        .map(|(c, n)| (c.r, n))
+        .collect();
+    assert_eq!(counts, vec![(8, 3), (200, 1)]);
+    let colors: Vec<_> = kmeans.working_colors().iter().map(|c| c.r).collect();
+    assert_eq!(colors, vec![8, 200]);
+}
+
+#[test]
+fn convergence_has_the_wcss_of_every_iteration() {
+    let pixels: Vec<(u8, u8, u8)> = (0..64)
+        .map(|i| ((i * 37 % 256) as u8, (i * 11 % 256) as u8, (i * 5) as u8))
+        .collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 8, 8).unwrap();
+    let config = Config {
+        seed: Some(2),
+        trace: true,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(4, Some(config)).unwrap();
+    let traced: Vec<f32> = result.trace.unwrap().iter().map(|t| t.wcss).collect();
+    assert_eq!(result.convergence, traced);
+    assert_eq!(result.convergence.len(), result.iterations);
+    assert_eq!(result.convergence.last(), Some(&result.wcss));
+
+    let config = Config {
+        algorithm: Algorithm::KMedoids,
+        ..Config::default()
+    };
+    let result = kmeans.with_fixed_k_number(4, Some(config)).unwrap();
+    assert!(result.convergence.is_empty());
+}
+
+#[test]
+fn region_weighting_counts_connected_regions() {
+    let (r, w, b) = ((255, 0, 0), (255, 255, 255), (0, 0, 255));
+    #[rustfmt::skip]
+    let pixels = [
+        r, r, w, b,
+        r, r, w, w,
+        w, b, w, b,
+        b, w, w, w,
+    ];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 4, 4).unwrap();
+    let mut counts = |region_weighting| {
+        let config = Config {
+            region_weighting,
+            ..Config::default()
+        };
+        kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+        let mut counts: Vec<_> = kmeans
+            .working_color_counts()
+            .into_iter()
+            .map(|(c, n)| ((c.r as u8, c.g as u8, c.b as u8), n))
+            .collect();
+        counts.sort();
+        counts
+    };
+
+    assert_eq!(
+        counts(RegionWeighting::Pixels),
+        vec![(b, 4), (r, 4), (w, 8)]
+    );
+    assert_eq!(
+        counts(RegionWeighting::LargestRegion),
+        vec![(b, 1), (r, 4), (w, 7)]
+    );
+    assert_eq!(
+        counts(RegionWeighting::RegionCount),
+        vec![(b, 4), (r, 1), (w, 2)]
+    );
+
+    let config = Config {
+        region_weighting: RegionWeighting::RegionCount,
+        coherence_weight: 1,
+        ..Config::default()
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(3, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn float_centroids_keep_the_unrounded_means() {
+    let data = rgba(&[(10, 0, 0), (11, 0, 0), (200, 0, 200), (200, 0, 200)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+
+    let result = kmeans.with_fixed_k_number(2, None).unwrap();
+    assert!(result.float_centroids.is_none());
+
+    let config = Config {
+        float_centroids: true,
+        snap_only_final: true,
+        ..Config::default()
+    };
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let mut pairs: Vec<((i32, i32, i32), [f32; 3])> = result
+        .clusters
+        .iter()
+        .map(|c| (c.r, c.g, c.b))
+        .zip(result.float_centroids.unwrap())
+        .collect();
+    pairs.sort_by_key(|&(c, _)| c);
+
+    assert_eq!(
+        pairs,
+        vec![
+            ((10, 0, 0), [10.5, 0.0, 0.0]),
+            ((200, 0, 200), [200.0, 0.0, 200.0])
+        ]
+    );
+}
+
+#[test]
+fn color_cube_bins_the_working_color_counts() {
+    let data = rgba(&[(0, 0, 0), (0, 0, 0), (255, 255, 255), (200, 0, 0)]);
+    let kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+
+    assert_eq!(kmeans.color_cube(2).unwrap(), vec![2, 0, 0, 0, 1, 0, 0, 1]);
+    assert_eq!(kmeans.color_cube(1).unwrap(), vec![4]);
+    assert_eq!(kmeans.color_cube(256).unwrap().iter().sum::<u32>(), 4);
+
+    for bins in [0, 257, 1 << 22] {
+        assert!(matches!(
+            kmeans.color_cube(bins),
+            Err(KmeansError::InvalidConfig { .. })
+        ));
+    }
+}
+
+#[test]
+fn plateau_threshold_stops_the_derived_scan_early() {
+    let pixels: Vec<(u8, u8, u8)> = (0..4).flat_map(|i| [(i, 0, 0), (200 + i, 0, 0)]).collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 8, 1).unwrap();
+
+    let full = kmeans.with_derived_k_number(None).unwrap();
+    assert_eq!(kmeans.elbow_curve().points.len(), 8);
+
+    let config = Config {
+        plateau_threshold: Some(0.9),
+        ..Config::default()
+    };
+    let result = kmeans.with_derived_k_number(Some(config)).unwrap();
+    let ks: Vec<usize> = kmeans.elbow_curve().points.iter().map(|p| p.0).collect();
+    assert_eq!(ks, vec![1, 2, 3, 4]);
+    assert_eq!(result.ks, 2);
+    assert_eq!(full.ks, 2);
+
+    let config = Config {
+        plateau_threshold: Some(1.0),
+        ..Config::default()
+    };
+    assert!(matches!(
+        kmeans.with_derived_k_number(Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn derived_k_stops_when_cancelled() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+
+    let cancelled = kmeans.with_derived_k_number_cancellable(None, || true);
+    assert!(matches!(cancelled, Err(KmeansError::Cancelled)));
+    assert!(kmeans.elbow_curve().points.is_empty());
+
+    let mut calls = 0;
+    let result = kmeans.with_derived_k_number_cancellable(None, || {
+        calls += 1;
+        false
+    });
+    assert!(result.is_ok());
+    assert!(calls >= 4);
+}
+
+#[test]
+fn render_quantized_scales_to_the_requested_size() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    kmeans.with_fixed_k_number(2, None).unwrap();
+
+    assert_eq!(kmeans.render_quantized(2, 2), kmeans.recolored_rgba());
+
+    let scaled = kmeans.render_quantized(4, 2);
+    let reds: Vec<u8> = scaled.chunks(4).map(|px| px[0]).collect();
+    assert_eq!(reds, vec![5, 5, 5, 5, 205, 205, 205, 205]);
+    assert!(kmeans.render_quantized(0, 3).is_empty());
+}
+
+#[test]
+fn rendering_assigns_pixels_as_the_run_did() {
+    let pixels: Vec<(u8, u8, u8)> = (0..64_u32)
+        .map(|i| {
+            (
+                (i * 37 % 256) as u8,
+                (i * 91 % 256) as u8,
+                (i * 13 % 256) as u8,
+            )
+        })
+        .collect();
+    let data = rgba(&pixels);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 8, 8).unwrap();
+    let config = Config {
+        color_space: ColorSpace::Lch,
+        seed: Some(3),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(5, Some(config)).unwrap();
+    let rendered = kmeans.render_quantized(8, 8);
+    let counts: Vec<usize> = result
+        .clusters
+        .iter()
+        .map(|k| {
+            rendered
+                .chunks(4)
+                .filter(|px| px[..3] == [k.r as u8, k.g as u8, k.b as u8])
+                .count()
+        })
+        .collect();
+    assert_eq!(counts, result.counts);
+    assert_eq!(
+        kmeans.recolor_frames(&[&data], 8, 8).unwrap(),
+        vec![rendered]
+    );
+}
+
+#[test]
+fn default_config_matches_running_without_one() {
+    let config = default_config();
+    assert!(config.validate().is_ok());
+    assert!(config == Config::default());
+
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+    assert_eq!(kmeans.quantize_fact(), 1);
+}
+
+#[test]
+fn fuzzy_c_means_finds_separated_groups() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    let config = Config {
+        algorithm: Algorithm::FuzzyCMeans { m: 2.0 },
+        seed: Some(1),
+        init: InitMethod::Deterministic,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let mut reds: Vec<i32> = result.clusters.iter().map(|c| c.r).collect();
+    reds.sort();
+    // every color pulls on both clusters a little
+    assert!((5..10).contains(&reds[0]), "{:?}", reds);
+    assert!((200..=205).contains(&reds[1]), "{:?}", reds);
+    assert!(result.wcss > 0.0);
+
+    let config = Config {
+        algorithm: Algorithm::FuzzyCMeans { m: 1.0 },
+        ..Config::default()
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(2, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn top_chromatic_keeps_rare_accents() {
+    let mut pixels = vec![(128, 128, 128); 6];
+    pixels.extend([(100, 100, 100); 3]);
+    pixels.extend([(90, 90, 90); 2]);
+    pixels.push((255, 0, 0));
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 12, 1).unwrap();
+    let config = Config {
+        top_num: Some(2),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config.clone())).unwrap();
+    assert!(result.clusters.iter().all(|c| c.r == c.g));
+
+    let config = Config {
+        top_chromatic: Some(1),
+        ..config
+    };
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+    assert!(result
+        .clusters
+        .iter()
+        .any(|c| (c.r, c.g, c.b) == (255, 0, 0)));
+}
+
+#[test]
+fn centroid_history_runs_from_initial_to_final_clusters() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    kmeans.set_random_source(Box::new(FirstPick));
+
+    let result = kmeans.with_fixed_k_number(2, None).unwrap();
+    assert!(result.centroid_history.is_none());
+
+    let config = Config {
+        centroid_history: true,
+        ..Config::default()
+    };
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let history = result.centroid_history.unwrap();
+
+    assert!(history.len() >= 2);
+    assert!(result.trace.is_none());
+    assert!(history.last().unwrap() == &result.clusters);
+    assert!(history[0] != result.clusters);
+}
+
+#[test]
+fn maximize_spread_picks_far_apart_colors() {
+    let mut pixels = vec![(0, 0, 0); 4];
+    pixels.extend([(20, 0, 0), (40, 0, 0), (60, 0, 0), (255, 255, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 8, 1).unwrap();
+    let config = Config {
+        maximize_spread: true,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+    let clusters: Vec<(i32, i32, i32)> = result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    assert_eq!(clusters, vec![(0, 0, 0), (255, 255, 255), (60, 0, 0)]);
+
+    let config = Config {
+        maximize_spread: true,
+        min_separation: Some(10.0),
+        ..Config::default()
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(3, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn maximize_spread_is_measured_in_the_color_space() {
+    let mut pixels = vec![(0, 0, 0); 4];
+    pixels.extend([(0, 0, 255), (255, 255, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 6, 1).unwrap();
+    // white is furthest from black in RGB but blue is in LCh
+    let spread = |kmeans: &mut ImageKmeans, color_space| {
+        let config = Config {
+            color_space,
+            maximize_spread: true,
+            ..Config::default()
+        };
+        let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+        let clusters: Vec<(i32, i32, i32)> =
+            result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+        clusters
+    };
+
+    assert_eq!(
+        spread(&mut kmeans, ColorSpace::Rgb),
+        vec![(0, 0, 0), (255, 255, 255)]
+    );
+    assert_eq!(
+        spread(&mut kmeans, ColorSpace::Lch),
+        vec![(0, 0, 0), (0, 0, 255)]
+    );
+}
+
+#[test]
+fn first_appearance_orders_clusters_by_scan_order() {
+    let data = rgba(&[(200, 0, 0), (0, 0, 200), (200, 0, 0), (0, 200, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    let config = Config {
+        sort_order: SortOrder::FirstAppearance,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+    let clusters: Vec<(i32, i32, i32)> = result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    assert_eq!(clusters, vec![(200, 0, 0), (0, 0, 200), (0, 200, 0)]);
+    assert_eq!(result.counts, vec![2, 1, 1]);
+}
+
+#[test]
+fn first_appearance_follows_the_run_color_space() {
+    let pixels: Vec<(u8, u8, u8)> = (0..64_u32)
+        .map(|i| {
+            (
+                (i * 37 % 256) as u8,
+                (i * 91 % 256) as u8,
+                (i * 13 % 256) as u8,
+            )
+        })
+        .collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 8, 8).unwrap();
+    let config = Config {
+        color_space: ColorSpace::Lch,
+        sort_order: SortOrder::FirstAppearance,
+        seed: Some(3),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let mut first = vec![usize::MAX; result.clusters.len()];
+    for (pixel, &(r, g, b)) in pixels.iter().enumerate().rev() {
+        let color = Color {
+            r: r as i32,
+            g: g as i32,
+            b: b as i32,
+        };
+        let distance = |k: &Color| color.distance_to(k, ColorSpace::Lch);
+        let nearest = (0..result.clusters.len())
+            .min_by(|&a, &b| {
+                distance(&result.clusters[a]).total_cmp(&distance(&result.clusters[b]))
+            })
+            .unwrap();
+        first[nearest] = pixel;
+    }
+
+    let mut sorted = first.clone();
+    sorted.sort();
+    assert_eq!(first, sorted);
+}
+
+#[test]
+fn apply_gamma_clusters_adjusted_channels() {
+    let data = rgba(&[(0, 0, 0), (255, 255, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+    let config = Config {
+        apply_gamma: Some(2.0),
+        ..Config::default()
+    };
+
+    let plain = kmeans.with_fixed_k_number(1, None).unwrap();
+    assert_eq!(plain.clusters[0].r, 127);
+    // the mean is taken of the squared channels
+    let result = kmeans.with_fixed_k_number(1, Some(config.clone())).unwrap();
+    assert_eq!(result.clusters[0].r, 180);
+
+    let data = rgba(&[(0, 0, 0), (255, 255, 255), (128, 64, 200)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 1).unwrap();
+    let result = kmeans.with_fixed_k_number(3, Some(config)).unwrap();
+    assert!(result
+        .clusters
+        .iter()
+        .any(|c| (c.r, c.g, c.b) == (128, 64, 200)));
+}
+
+#[test]
+fn hsv_clusters_snap_to_real_colors() {
+    let pixels = [(200, 40, 90), (90, 200, 40), (40, 90, 200), (255, 255, 0)];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 4, 1).unwrap();
+    let config = Config {
+        color_space: ColorSpace::Hsv,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(4, Some(config)).unwrap();
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    let mut expected: Vec<(i32, i32, i32)> = pixels
+        .iter()
+        .map(|&(r, g, b)| (r as i32, g as i32, b as i32))
+        .collect();
+    expected.sort();
+    assert_eq!(clusters, expected);
+
+    let data = rgba(&[(255, 0, 60), (255, 0, 60), (255, 60, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 1).unwrap();
+    let config = Config {
+        color_space: ColorSpace::Hsv,
+        snap_only_final: true,
+        ..Config::default()
+    };
+    let result = kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+    assert_eq!(
+        (
+            result.clusters[0].r,
+            result.clusters[0].g,
+            result.clusters[0].b
+        ),
+        (255, 0, 60)
+    );
+}
+
+#[test]
+fn palette_iter_yields_indexed_entries() {
+    let data = rgba(&[(255, 0, 0), (255, 0, 0), (255, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+
+    assert_eq!(kmeans.palette_iter().count(), 0);
+
+    let result = kmeans.with_fixed_k_number(2, None).unwrap();
+    for (entry, cluster) in kmeans.palette_iter().zip(&result.clusters) {
+        assert!(entry.color == *cluster);
+        assert_eq!(entry.count, result.counts[entry.index]);
+    }
+    let largest = kmeans.palette_iter().max_by_key(|e| e.count).unwrap();
+    assert_eq!((largest.hex.as_str(), largest.fraction), ("#FF0000", 0.75));
+}
+
+#[test]
+fn premultiplied_alpha_recovers_original_colors() {
+    // (200, 100, 50) and (0, 50, 250) at 20% and 60% alpha, then a transparent pixel
+    let data = vec![40, 20, 10, 51, 0, 30, 150, 153, 90, 90, 90, 0];
+    let mut kmeans = ImageKmeans::from_rgba(&data, 3, 1).unwrap();
+    let config = Config {
+        alpha_mode: AlphaMode::Premultiplied,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    assert_eq!(clusters, vec![(0, 50, 250), (200, 100, 50)]);
+    assert_eq!(result.counts.iter().sum::<usize>(), 2);
+}
+
+#[test]
+fn iteration_limits_bound_every_run() {
+    let data = rgba(&[(0, 0, 0), (10, 0, 0), (200, 0, 0), (210, 0, 0)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+    let limits = |min, max| Config {
+        min_iterations: min,
+        max_iterations: max,
+        seed: Some(3),
+        ..Config::default()
+    };
+
+    let result = kmeans
+        .with_fixed_k_number(2, Some(limits(None, None)))
+        .unwrap();
+    assert!(result.iterations < 5);
+    let result = kmeans
+        .with_fixed_k_number(2, Some(limits(Some(5), None)))
+        .unwrap();
+    assert_eq!(result.iterations, 5);
+    let result = kmeans
+        .with_fixed_k_number(2, Some(limits(None, Some(1))))
+        .unwrap();
+    assert_eq!(result.iterations, 1);
+
+    assert!(matches!(
+        kmeans.with_fixed_k_number(2, Some(limits(Some(3), Some(2)))),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+    assert!(matches!(
+        kmeans.with_fixed_k_number(2, Some(limits(None, Some(0)))),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn display_palette_gives_distinct_colors_by_index() {
+    let data = rgba(&[(200, 30, 30), (205, 30, 30), (210, 30, 30), (40, 40, 40)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+
+    let empty = kmeans.display_palette();
+    assert!(empty.palette.is_empty() && empty.display.is_empty());
+
+    let result = kmeans.with_fixed_k_number(4, None).unwrap();
+    let display = kmeans.display_palette();
+
+    assert!(display.palette == result.clusters);
+    assert_eq!(display.display.len(), 4);
+    let mut distinct: Vec<(i32, i32, i32)> =
+        display.display.iter().map(|c| (c.r, c.g, c.b)).collect();
+    distinct.sort();
+    distinct.dedup();
+    assert_eq!(distinct.len(), 4);
+}
+
+#[test]
+fn derived_k_is_stable_across_color_spaces() {
+    let mut pixels = vec![];
+    for (r, g, b) in [(220, 30, 30), (30, 180, 40), (40, 60, 220)] {
+        for i in 0..4 {
+            pixels.push((r + i * 3, g + i * 2, b));
+        }
+    }
+    let data = rgba(&pixels);
+
+    for color_space in [ColorSpace::Rgb, ColorSpace::Lch, ColorSpace::Hsv] {
+        let mut kmeans = ImageKmeans::from_rgba(&data, 12, 1).unwrap();
+        let config = Config {
+            color_space,
+            init: InitMethod::KmeansPlusPlus,
+            seed: Some(5),
+            ..Config::default()
+        };
+
+        let result = kmeans.with_derived_k_number(Some(config)).unwrap();
+        assert_eq!(result.ks, 3, "{:?}", color_space);
+    }
+}
+
+#[test]
+fn quantize_to_palette_maps_onto_reference_colors() {
+    let data = rgba(&[(10, 0, 0), (240, 250, 255), (250, 250, 250), (0, 40, 0)]);
+    let kmeans = ImageKmeans::from_rgba(&data, 4, 1).unwrap();
+
+    assert_eq!(Color::web_safe().len(), 216);
+
+    let palette = [
+        Color { r: 255, g: 0, b: 0 },
+        Color { r: 0, g: 0, b: 0 },
+        Color {
+            r: 255,
+            g: 255,
+            b: 255,
+        },
+    ];
+    let result = kmeans.quantize_to_palette(&palette).unwrap();
+    let clusters: Vec<(i32, i32, i32)> = result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    assert_eq!(clusters, vec![(0, 0, 0), (255, 255, 255)]);
+    assert_eq!(result.counts, vec![2, 2]);
+    assert_eq!(result.ks, 2);
+    assert_eq!(result.wcss, 100.0 + 1600.0 + 250.0 + 75.0);
+
+    let web_safe = kmeans.quantize_to_web_safe();
+    assert_eq!(web_safe.counts.iter().sum::<usize>(), 4);
+}
+
+#[test]
+fn saturation_scale_boosts_muted_colors() {
+    let data = rgba(&[(150, 100, 100), (128, 128, 128)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+    let config = Config {
+        saturation_scale: Some(2.0),
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    assert_eq!(clusters, vec![(128, 128, 128), (150, 50, 50)]);
+}
+
+#[test]
+fn quantization_sweep_runs_once_per_factor() {
+    let pixels: Vec<(u8, u8, u8)> = (0..16).map(|i| (i * 16, 0, 255 - i * 16)).collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 16, 1).unwrap();
+    let config = Config {
+        seed: Some(2),
+        ..Config::default()
+    };
+
+    let results = kmeans
+        .quantization_sweep(vec![1, 16, 64], 3, Some(config.clone()))
+        .unwrap()
+        .0;
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|r| r.ks == 3));
+    assert_eq!(kmeans.quantize_fact(), 64);
+    assert!(results[0].clusters != results[2].clusters);
+
+    let config = Config {
+        target_distinct: Some(4),
+        ..config
+    };
+    assert!(matches!(
+        kmeans.quantization_sweep(vec![1], 3, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn normalized_elbow_isnt_dominated_by_k_one() {
+    let mut pixels = vec![(0, 0, 0); 50];
+    pixels.extend([(255, 255, 255); 50]);
+    pixels.extend([(250, 0, 0), (250, 0, 0), (240, 10, 0), (240, 10, 0)]);
+    pixels.push((0, 0, 250));
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 105, 1).unwrap();
+    let config = Config {
+        init: InitMethod::Deterministic,
+        ..Config::default()
+    };
+
+    // the k = 1 WCSS is ~18 times that of k = 2, flattening the rest
+    let raw = kmeans.with_derived_k_number(Some(config.clone())).unwrap();
+    assert_eq!(raw.ks, 2);
+
+    let config = Config {
+        normalize_elbow: true,
+        ..config
+    };
+    let normalized = kmeans.with_derived_k_number(Some(config)).unwrap();
+    assert_eq!(normalized.ks, 4);
+}
+
+#[test]
+fn pixel_weights_pull_the_means() {
+    let data = rgba(&[(0, 0, 0), (200, 200, 200), (255, 0, 0)]);
+
+    let mut kmeans = ImageKmeans::from_rgba_weighted(&data, 3, 1, &[1.0, 3.0, 0.0]).unwrap();
+    let result = kmeans.with_fixed_k_number(1, None).unwrap();
+    let c = &result.clusters[0];
+    assert_eq!((c.r, c.g, c.b), (150, 150, 150));
+    // counts stay pixel counts, the zero weighted pixel is left out
+    assert_eq!(result.counts, vec![2]);
+    assert_eq!(result.wcss, 3.0 * 3.0 * 50.0 * 50.0 + 3.0 * 150.0 * 150.0);
+
+    assert!(matches!(
+        ImageKmeans::from_rgba_weighted(&data, 3, 1, &[1.0, 1.0]),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+    assert!(matches!(
+        ImageKmeans::from_rgba_weighted(&data, 3, 1, &[1.0, -1.0, 1.0]),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn palette_png_encodes_a_row_of_swatches() {
+    let data = rgba(&[(255, 0, 0), (0, 0, 255), (255, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    assert!(kmeans.palette_png(4).is_empty());
+
+    let result = kmeans.with_fixed_k_number(2, None).unwrap();
+    let png = kmeans.palette_png(3);
+    assert!(kmeans.palette_png(0).is_empty());
+
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 6, 0, 0, 0, 3]);
+    // IEND with its well known CRC
+    assert_eq!(&png[png.len() - 8..], b"IEND\xae\x42\x60\x82");
+
+    // a single stored deflate block after the zlib header
+    let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+    assert_eq!(&png[37..41], b"IDAT");
+    let zlib = &png[41..41 + idat_len];
+    let len = u16::from_le_bytes([zlib[3], zlib[4]]) as usize;
+    let scanlines = &zlib[7..7 + len];
+    assert_eq!(scanlines.len(), 3 * (1 + 6 * 3));
+
+    let first = &result.clusters[0];
+    let second = &result.clusters[1];
+    for line in scanlines.chunks(19) {
+        assert_eq!(line[0], 0);
+        assert_eq!(&line[1..4], &[first.r as u8, first.g as u8, first.b as u8]);
+        assert_eq!(
+            &line[16..19],
+            &[second.r as u8, second.g as u8, second.b as u8]
+        );
+    }
+}
+
+#[test]
+fn grayscale_split_clusters_greys_and_colors_separately() {
+    let mut pixels = vec![(0, 0, 0); 10];
+    pixels.extend([(250, 250, 245); 10]);
+    pixels.extend([(250, 0, 0); 10]);
+    pixels.extend([(0, 0, 250); 10]);
+    pixels.extend([(140, 128, 128); 2]);
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 42, 1).unwrap();
+    let config = Config {
+        grayscale_split: Some(GrayscaleSplit {
+            tolerance: 12,
+            grayscale_fraction: 0.5,
+        }),
+        init: InitMethod::Deterministic,
+        ..Config::default()
+    };
+
+    let result = kmeans.with_fixed_k_number(4, Some(config.clone())).unwrap();
+    assert_eq!(result.ks, 4);
+    let grays: Vec<bool> = result
+        .clusters
+        .iter()
+        .map(|c| c.r.max(c.g).max(c.b) - c.r.min(c.g).min(c.b) <= 12)
+        .collect();
+    assert_eq!(grays, vec![true, true, false, false]);
+    assert_eq!(result.counts.iter().sum::<usize>(), 42);
+    assert_eq!(result.counts[2] + result.counts[3], 20);
+
+    // a single color kind gets all of k
+    let mut greys =
+        ImageKmeans::from_rgba(&rgba(&[(0, 0, 0), (90, 90, 90), (255, 255, 255)]), 3, 1).unwrap();
+    assert_eq!(
+        greys
+            .with_fixed_k_number(3, Some(config.clone()))
+            .unwrap()
+            .ks,
+        3
+    );
+
+    let invalid = Config {
+        trace: true,
+        ..config
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(4, Some(invalid)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn kneedle_finds_the_knee_of_a_near_linear_fall() {
+    let groups = [
+        ((129, 159, 61), 17),
+        ((148, 67, 87), 39),
+        ((50, 82, 81), 7),
+        ((104, 104, 191), 37),
+        ((187, 184, 193), 33),
+    ];
+    let mut pixels = vec![];
+    for ((r, g, b), n) in groups {
+        for i in 0..n {
+            pixels.push((r + i % 3 * 3, g + i / 3 % 3 * 3, b));
+        }
+    }
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), pixels.len() as u32, 1).unwrap();
+    let config = Config {
+        init: InitMethod::KmeansPlusPlus,
+        seed: Some(1),
+        ..Config::default()
+    };
+
+    // the WCSS falls fairly evenly until all five groups are found, which
+    // the elbow cuts short
+    let elbow = kmeans.with_derived_k_number(Some(config.clone())).unwrap();
+    assert_eq!(elbow.ks, 3);
+
+    let kneedle = Config {
+        k_selection: KSelection::Kneedle { sensitivity: 1.0 },
+        ..config.clone()
+    };
+    let result = kmeans.with_derived_k_number(Some(kneedle)).unwrap();
+    assert_eq!(result.ks, 5);
+    let detail = kmeans.selection_detail().unwrap();
+    assert_eq!(detail.chosen_k, 5);
+    assert_eq!(detail.distances[0], 0.0);
+
+    // too insensitive to find any knee
+    let insensitive = Config {
+        k_selection: KSelection::Kneedle { sensitivity: 100.0 },
+        ..config.clone()
+    };
+    assert_eq!(
+        kmeans.with_derived_k_number(Some(insensitive)).unwrap().ks,
+        1
+    );
+
+    let invalid = Config {
+        k_selection: KSelection::Kneedle { sensitivity: -1.0 },
+        ..config
+    };
+    assert!(matches!(
+        kmeans.with_derived_k_number(Some(invalid)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn measured_runs_carry_timings() {
+    let data = rgba(&[(255, 0, 0), (0, 0, 255), (250, 0, 0), (0, 0, 250)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+
+    assert!(kmeans
+        .with_fixed_k_number(2, None)
+        .unwrap()
+        .timing
+        .is_none());
+
+    let config = Config {
+        measure: true,
+        ..Config::default()
+    };
+    let timing = kmeans
+        .with_fixed_k_number(2, Some(config.clone()))
+        .unwrap()
+        .timing
+        .unwrap();
+    assert!(timing.working_colors_ms >= 0.0);
+    assert!(timing.init_ms >= 0.0);
+    assert!(timing.iterations_ms >= 0.0);
+
+    let derived = kmeans.with_derived_k_number(Some(config)).unwrap();
+    assert!(derived.timing.is_some());
+}
+
+#[test]
+fn alternative_ks_ranks_derived_runs_by_distance() {
+    let pixels: Vec<(u8, u8, u8)> = (0..40).map(|i| ((i % 4) * 80, i % 3, 0)).collect();
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 40, 1).unwrap();
+    assert!(kmeans.alternative_ks(3).0.is_empty());
+
+    let result = kmeans.with_derived_k_number(None).unwrap();
+    let detail = kmeans.selection_detail().unwrap();
+    let alternatives = kmeans.alternative_ks(3).0;
+
+    assert_eq!(alternatives.len(), 3);
+    assert_eq!(alternatives[0].ks, result.ks);
+    let distance = |ks: usize| detail.distances[ks - 1];
+    assert!(distance(alternatives[0].ks) >= distance(alternatives[1].ks));
+    assert!(distance(alternatives[1].ks) >= distance(alternatives[2].ks));
+    assert_eq!(kmeans.alternative_ks(100).0.len(), detail.distances.len());
+
+    kmeans.with_fixed_k_number(2, None).unwrap();
+    assert!(kmeans.alternative_ks(3).0.is_empty());
+}
+
+#[test]
+fn downsampled_pixels_average_their_blocks() {
+    // a thin blue line a stride of 2 would skip entirely
+    let data = rgba(&[
+        (255, 0, 0),
+        (0, 0, 255),
+        (0, 255, 0),
+        (0, 255, 0),
+        (255, 0, 0),
+        (0, 0, 255),
+        (0, 255, 0),
+        (0, 255, 0),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba_downsampled(&data, 4, 2, 2, 1).unwrap();
+    assert_eq!(kmeans.sample_rate(), 0.25);
+
+    let result = kmeans.with_fixed_k_number(2, None).unwrap();
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    assert_eq!(clusters, vec![(0, 255, 0), (128, 0, 128)]);
+
+    // transparent pixels don't darken the average
+    let data = [255, 0, 0, 255, 255, 255, 255, 0];
+    let mut kmeans = ImageKmeans::from_rgba_downsampled(&data, 2, 1, 1, 1).unwrap();
+    let c = kmeans.with_fixed_k_number(1, None).unwrap().clusters[0].clone();
+    assert_eq!((c.r, c.g, c.b), (255, 0, 0));
+
+    assert!(matches!(
+        ImageKmeans::from_rgba_downsampled(&data, 2, 1, 0, 1),
+        Err(KmeansError::InvalidDimensions)
+    ));
+}
+
+#[test]
+fn jitter_breaks_exact_ties() {
+    let mut pixels = vec![(0, 0, 0); 20];
+    pixels.extend([(100, 0, 0); 30]);
+    // all exactly as near black as the initial (100, 0, 0)
+    pixels.extend([
+        (50, 0, 0),
+        (50, 6, 0),
+        (50, 0, 6),
+        (50, 6, 6),
+        (50, 3, 3),
+        (50, 9, 0),
+    ]);
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 56, 1).unwrap();
+    let config = Config {
+        locked_colors: vec![Color { r: 0, g: 0, b: 0 }],
+        init: InitMethod::Deterministic,
+        ..Config::default()
+    };
+
+    let plain = kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    assert_eq!(plain.counts, vec![26, 30]);
+    let c = &plain.clusters[1];
+    assert_eq!((c.r, c.g, c.b), (100, 0, 0));
+
+    let config = Config {
+        jitter: Some(0.01),
+        ..config
+    };
+    let jittered = kmeans.with_fixed_k_number(2, Some(config.clone())).unwrap();
+    assert_eq!(jittered.counts, vec![20, 36]);
+    assert!(jittered.wcss < plain.wcss);
+
+    let again = kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+    assert!(again.clusters == jittered.clusters);
+}
+
+#[test]
+fn out_of_range_channels_are_rejected() {
+    assert_eq!(CHANNEL_MAX, 255);
+    assert!(Color {
+        r: 0,
+        g: 128,
+        b: 255
+    }
+    .in_range());
+    assert!(!Color { r: 300, g: 0, b: 0 }.in_range());
+    assert!(!Color { r: 0, g: -1, b: 0 }.in_range());
+
+    let data = rgba(&[(255, 0, 0), (0, 0, 255)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+    let bad = [Color { r: 300, g: 0, b: 0 }];
+
+    assert!(matches!(
+        kmeans.quantize_to_palette(&bad),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+    assert!(matches!(
+        kmeans.score_palette(&bad),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+    let config = Config {
+        locked_colors: bad.to_vec(),
+        ..Config::default()
+    };
+    assert!(matches!(
+        kmeans.with_fixed_k_number(2, Some(config)),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn recolor_frames_share_the_latest_palette() {
+    let data = rgba(&[(255, 0, 0), (0, 0, 255), (250, 0, 0), (0, 0, 250)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 2).unwrap();
+    let first = [240, 10, 0, 255, 10, 0, 240, 128];
+    let second = [0, 0, 0, 255, 255, 255, 255, 255];
+    assert!(matches!(
+        kmeans.recolor_frames(&[&first], 2, 1),
+        Err(KmeansError::NoRun)
+    ));
+
+    let result = kmeans.with_fixed_k_number(2, None).unwrap();
+    let red = result.clusters.iter().find(|c| c.r > c.b).unwrap().clone();
+    let blue = result.clusters.iter().find(|c| c.b > c.r).unwrap().clone();
+
+    let frames = kmeans.recolor_frames(&[&first, &second], 2, 1).unwrap();
+    assert_eq!(
+        frames[0],
+        vec![
+            red.r as u8,
+            red.g as u8,
+            red.b as u8,
+            255,
+            blue.r as u8,
+            blue.g as u8,
+            blue.b as u8,
+            128
+        ]
+    );
+    assert_eq!(frames[1].len(), 8);
+
+    assert!(matches!(
+        kmeans.recolor_frames(&[&first, &second[..4]], 2, 1),
+        Err(KmeansError::InvalidDimensions)
+    ));
+}
+
+#[test]
+fn color_distances_follow_the_color_space() {
+    let red = Color { r: 255, g: 0, b: 0 };
+    let blue = Color { r: 0, g: 0, b: 255 };
+
+    assert_eq!(red.squared_distance_to(&blue, ColorSpace::Rgb), 130050.0);
+    assert_eq!(red.distance_to(&blue, ColorSpace::Rgb), 130050_f32.sqrt());
+    assert_eq!(red.distance_to(&red, ColorSpace::Lch), 0.0);
+
+    let lch = red.distance_to(&blue, ColorSpace::Lch);
+    assert!((red.squared_distance_to(&blue, ColorSpace::Lch) - lch * lch).abs() < 1.0);
+    assert!(lch != red.distance_to(&blue, ColorSpace::Rgb));
+}
+
+#[test]
+fn integer_rgb_nearest_matches_the_float_search() {
+    let mut state = 7_u32;
+    let mut channel = || {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (state >> 24) as i32
+    };
+    let mut random_color = || Color {
+        r: channel(),
+        g: channel(),
+        b: channel(),
+    };
+    let mut candidates: Vec<Color> = (0..16).map(|_| random_color()).collect();
+    // a repeated candidate ties with its first copy
+    candidates.push(candidates[3].clone());
+
+    for _ in 0..500 {
+        let color = random_color();
+        let distances: Vec<f32> = candidates
+            .iter()
+            .map(|k| color.distance_to(k, ColorSpace::Rgb))
+            .collect();
+        let mut expected = 0;
+        for (i, &d) in distances.iter().enumerate() {
+            if d < distances[expected] {
+                expected = i;
+            }
+        }
+        assert_eq!(
+            ColorSpace::Rgb.nearest_color(&color, &candidates),
+            (expected, distances[expected])
+        );
+    }
+
+    // equally near candidates go to the earliest
+    let color = Color {
+        r: 10,
+        g: 10,
+        b: 10,
+    };
+    let tied = [
+        Color {
+            r: 20,
+            g: 10,
+            b: 10,
+        },
+        Color { r: 10, g: 0, b: 10 },
+        Color {
+            r: 10,
+            g: 10,
+            b: 20,
+        },
+    ];
+    assert_eq!(ColorSpace::Rgb.nearest_color(&color, &tied), (0, 10.0));
+    assert_eq!(ColorSpace::Rgb.nearest_color(&color, &tied[1..]), (0, 10.0));
+}
+
+#[test]
+fn color_distances_follow_the_distance_metric() {
+    let red = Color { r: 255, g: 0, b: 0 };
+    let orange = Color {
+        r: 200,
+        g: 50,
+        b: 0,
+    };
+    let (rgb, chebyshev) = (ColorSpace::Rgb, DistanceMetric::Chebyshev);
+
+    assert_eq!(red.distance_with(&orange, rgb, chebyshev), 55.0);
+    assert_eq!(red.squared_distance_with(&orange, rgb, chebyshev), 3025.0);
+    assert_eq!(
+        red.distance_with(&orange, ColorSpace::Lch, DistanceMetric::Euclidean),
+        red.distance_to(&orange, ColorSpace::Lch)
+    );
+
+    // the same squared distances a Chebyshev run sums into its WCSS
+    let pixels = [(0, 0, 0), (10, 4, 0)];
+    let mut kmeans = ImageKmeans::from_rgba(&rgba(&pixels), 2, 1).unwrap();
+    let config = Config {
+        distance_metric: chebyshev,
+        ..Config::default()
+    };
+    let result = kmeans.with_fixed_k_number(1, Some(config)).unwrap();
+    let wcss: f32 = pixels
+        .iter()
+        .map(|&(r, g, b)| {
+            let color = Color {
+                r: r as i32,
+                g: g as i32,
+                b: b as i32,
+            };
+            color.squared_distance_with(&result.clusters[0], rgb, chebyshev)
+        })
+        .sum();
+    assert_eq!(result.wcss, wcss);
+    assert_eq!(wcss, 50.0);
+}
+
+#[test]
+fn online_updates_nudge_clusters_towards_the_frame() {
+    let data = rgba(&[(200, 0, 0), (0, 0, 200)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+    let frame = rgba(&[(100, 0, 0), (100, 0, 0), (0, 0, 100), (0, 0, 100)]);
+    assert!(matches!(
+        kmeans.update_online_rgba(&frame, 4, 1, 0.5),
+        Err(KmeansError::NoRun)
+    ));
+
+    kmeans.with_fixed_k_number(2, None).unwrap();
+    let result = kmeans.update_online_rgba(&frame, 4, 1, 0.5).unwrap();
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    assert_eq!(clusters, vec![(0, 0, 150), (150, 0, 0)]);
+    assert_eq!(result.counts, vec![2, 2]);
+    assert_eq!(result.wcss, 4.0 * 50.0 * 50.0);
+
+    // the latest result carries the update into the next frame
+    let result = kmeans.update_online_rgba(&frame, 4, 1, 1.0).unwrap();
+    assert_eq!(result.wcss, 0.0);
+    assert!(kmeans.palette_hex().contains(&"#640000".to_string()));
+
+    assert!(matches!(
+        kmeans.update_online_rgba(&frame, 4, 1, 0.0),
+        Err(KmeansError::InvalidConfig { .. })
+    ));
+}
+
+#[test]
+fn online_updates_measure_as_the_run_did() {
+    let data = rgba(&[(200, 0, 0), (0, 0, 200)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+    let config = Config {
+        distance_metric: DistanceMetric::Chebyshev,
+        trace: true,
+        ..Config::default()
+    };
+    kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+
+    let frame = rgba(&[(100, 30, 0), (100, 30, 0), (0, 0, 100), (0, 0, 100)]);
+    let result = kmeans.update_online_rgba(&frame, 4, 1, 0.5).unwrap();
+    let mut clusters: Vec<(i32, i32, i32)> =
+        result.clusters.iter().map(|c| (c.r, c.g, c.b)).collect();
+    clusters.sort();
+    assert_eq!(clusters, vec![(0, 0, 150), (150, 15, 0)]);
+    // every pixel is 50 away on its largest channel difference
+    assert_eq!(result.wcss, 4.0 * 50.0 * 50.0);
+    assert_eq!(result.iterations, 1);
+    assert_eq!(result.convergence, vec![result.wcss]);
+    assert!(result.trace.is_none());
+}
+
+#[test]
+fn online_updates_skip_transparent_pixels() {
+    let data = rgba(&[(200, 0, 0), (0, 0, 200)]);
+    let mut kmeans = ImageKmeans::from_rgba(&data, 2, 1).unwrap();
+    let config = Config {
+        alpha_mode: AlphaMode::SkipTransparent,
+        ..Config::default()
+    };
+    kmeans.with_fixed_k_number(2, Some(config)).unwrap();
+
+    // the hidden pixels would pull the red cluster towards green
+    let frame = [100, 0, 0, 255, 100, 0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0];
+    let result = kmeans.update_online_rgba(&frame, 4, 1, 1.0).unwrap();
+    let red = result.clusters.iter().position(|c| c.r > 0).unwrap();
+    assert_eq!((result.clusters[red].r, result.clusters[red].g), (100, 0));
+    assert_eq!(result.counts[red], 2);
+    assert_eq!(result.counts.iter().sum::<usize>(), 2);
+
+    let hidden = [0, 255, 0, 0, 0, 255, 0, 0];
+    let result = kmeans.update_online_rgba(&hidden, 2, 1, 1.0).unwrap();
+    assert_eq!(result.counts, vec![0, 0]);
+    assert_eq!(result.wcss, 0.0);
+}